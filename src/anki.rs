@@ -1,9 +1,13 @@
 use clap::ValueEnum;
 use std::{collections::HashSet, str::FromStr};
 
-use crate::dict::{Tone, Word};
+use crate::dict::{PinYin, Tone, Word};
+use crate::existing::ExistingNote;
+use crate::export::{Card, Exporter};
+use crate::pinyin::{ReadingStyle, Romanisation};
 
 use genanki_rs::{Deck, Field, Model, Note, Template};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone)]
 pub enum ToneColours {
@@ -13,10 +17,12 @@ pub enum ToneColours {
     On([String; 5]),
 }
 
-#[derive(Debug, Clone, ValueEnum)]
+#[derive(Debug, Clone, PartialEq, Eq, ValueEnum)]
 pub enum Side {
     CeToEn,
     EnToCe,
+    /// Type in the numbered pinyin for the 汉字 shown
+    TypePinyin,
 }
 impl FromStr for ToneColours {
     type Err = String;
@@ -57,7 +63,7 @@ impl Default for ToneColours {
 }
 
 impl ToneColours {
-    fn css(&self) -> String {
+    pub(crate) fn css(&self) -> String {
         match self {
             ToneColours::Off => ".tone1 {color: black;}
                  .tone2 {color: black;}
@@ -76,65 +82,147 @@ impl ToneColours {
     }
 }
 
-pub struct Anki {
-    model: Model,
-    deck: Deck,
+/// A card template: its name, and the HTML for the front and back of its cards.
+#[derive(Clone)]
+pub struct CardTemplate {
+    pub name: String,
+    pub front: String,
+    pub back: String,
 }
 
-impl Anki {
+/// The fields, card templates and styling of the notes made, along with how
+/// to fill in the fields for each card.
+#[derive(Clone)]
+pub struct NoteType {
+    pub fields: Vec<String>,
+    pub templates: Vec<CardTemplate>,
+    pub css: String,
+    tts: bool,
+    style: ReadingStyle,
+    romanisations: Vec<Romanisation>,
+    sort_key: bool,
+}
+
+impl NoteType {
     pub fn new(
-        deck_name: &str,
         tone_colours: &ToneColours,
-        side: &Option<Side>,
+        sides: &[Side],
         tts: bool,
+        style: ReadingStyle,
+        romanisations: &[Romanisation],
+        sort_key: bool,
     ) -> Self {
-        let en_to_ce = Template::new("Card 1")
-            .qfmt("<div>{{AllDefinitions}}</div>")
-            .afmt(&format!(
+        let (sound, example_sound) = if tts {
+            ("<br>{{Sound}}", " {{ExampleSound}}")
+        } else {
+            ("", "")
+        };
+        let romanisation_fields = romanisations
+            .iter()
+            .map(|r| format!("<div class=reading>{{{{{}}}}}</div>", Self::field_name(r)))
+            .collect::<String>();
+        let en_to_ce = CardTemplate {
+            name: "Card 1".into(),
+            front: "<div>{{AllDefinitions}}</div>".into(),
+            back: format!(
                 r#"
                 <div class=chinese>
                     <a href="plecoapi://x-callback-url/s?q={{{{Hanzi}}}}" style="text-decoration:none">
                         {{{{ColourHanzi}}}}
                     </a>
                 </div>
-                <div>{{{{AllDefinitionsWithPinyin}}}}</div>
-                <div class=comment>{{{{Example}}}}</div>{}
-                "#,
-                if tts { "<br>{{Sound}}" } else { "" }
-            ));
-        let ce_to_en = Template::new("Card 2")
-            .qfmt("<div class=chinese>{{Hanzi}}</div>")
-            .afmt(&format!(
+                <div>{{{{AllDefinitionsWithPinyin}}}}</div>{romanisation_fields}
+                <div class=comment>{{{{Example}}}}{example_sound}</div>{sound}
+                {{{{#Count}}}}<div class=count>Seen {{{{Count}}}} times in the text</div>{{{{/Count}}}}
+                "#
+            ),
+        };
+        let ce_to_en = CardTemplate {
+            name: "Card 2".into(),
+            front: "<div class=chinese>{{Hanzi}}</div>".into(),
+            back: format!(
                 r#"
                 <div class=chinese>
                     <a href="plecoapi://x-callback-url/s?q={{{{Hanzi}}}}" style="text-decoration:none">
                         {{{{ColourHanzi}}}}
                     </a>
                 </div>
-                <div>{{{{AllDefinitionsWithPinyin}}}}</div>
-                <div class=comment>{{{{Example}}}}</div>{}
-                "#,
-                if tts { "<br>{{Sound}}" } else { "" }
-            ));
-
-        let sides = match side {
-            Some(Side::CeToEn) => vec![ce_to_en],
-            Some(Side::EnToCe) => vec![en_to_ce],
-            None => vec![ce_to_en, en_to_ce],
+                <div>{{{{AllDefinitionsWithPinyin}}}}</div>{romanisation_fields}
+                <div class=comment>{{{{Example}}}}{example_sound}</div>{sound}
+                {{{{#Count}}}}<div class=count>Seen {{{{Count}}}} times in the text</div>{{{{/Count}}}}
+                "#
+            ),
         };
 
-        let mut fields = vec![
-            Field::new("AllDefinitions"),
-            Field::new("AllDefinitionsWithPinyin"),
-            Field::new("Hanzi"),
-            Field::new("ColourHanzi"),
-            Field::new("Example"),
-        ];
+        // Only generated for notes with a non-empty NumberedPinyin, since
+        // there's no single right answer to type for words with several readings.
+        // Without ce-to-en cards, those words get one of those instead.
+        let ce_to_en_fallback = CardTemplate {
+            front: format!(
+                "{{{{^NumberedPinyin}}}}{}{{{{/NumberedPinyin}}}}",
+                ce_to_en.front
+            ),
+            ..ce_to_en.clone()
+        };
+        let type_pinyin = CardTemplate {
+            name: "Card 3".into(),
+            front: "{{#NumberedPinyin}}<div class=chinese>{{Hanzi}}</div>{{type:NumberedPinyin}}{{/NumberedPinyin}}".into(),
+            back: format!(
+                r#"
+                <div class=chinese>
+                    <a href="plecoapi://x-callback-url/s?q={{{{Hanzi}}}}" style="text-decoration:none">
+                        {{{{ColourHanzi}}}}
+                    </a>
+                </div>
+                {{{{type:NumberedPinyin}}}}
+                <div>{{{{AllDefinitionsWithPinyin}}}}</div>{romanisation_fields}
+                <div class=comment>{{{{Example}}}}{example_sound}</div>{sound}
+                {{{{#Count}}}}<div class=count>Seen {{{{Count}}}} times in the text</div>{{{{/Count}}}}
+                "#
+            ),
+        };
+
+        let templates = if sides.is_empty() {
+            vec![ce_to_en, en_to_ce]
+        } else {
+            let mut templates = vec![];
+            if sides.contains(&Side::CeToEn) {
+                templates.push(ce_to_en);
+            }
+            if sides.contains(&Side::EnToCe) {
+                templates.push(en_to_ce);
+            }
+            if sides.contains(&Side::TypePinyin) {
+                templates.push(type_pinyin);
+                if !sides.contains(&Side::CeToEn) {
+                    templates.push(ce_to_en_fallback);
+                }
+            }
+            templates
+        };
+
+        let mut fields: Vec<String> = [
+            "AllDefinitions",
+            "AllDefinitionsWithPinyin",
+            "Hanzi",
+            "ColourHanzi",
+            "Example",
+            "NumberedPinyin",
+            "Count",
+        ]
+        .map(String::from)
+        .to_vec();
+        for romanisation in romanisations {
+            fields.push(Self::field_name(romanisation));
+        }
+        if sort_key {
+            fields.push("SortKey".into());
+        }
         if tts {
-            fields.push(Field::new("Sound"));
+            fields.push("Sound".into());
+            fields.push("ExampleSound".into());
         }
-        let model: Model = Model::new(1607392319, "Simple Model", fields, sides).css(
-            r#".card {
+        let css = r#".card {
             font-family: arial;
             font-size: 20px;
             text-align: center;
@@ -150,39 +238,91 @@ impl Anki {
         
         .reading { font-size: 16px;}
         .comment {font-size: 16px; color:grey;}
+        .count {font-size: 12px; color:grey; margin-top: 8px;}
         .tags {color:gray;text-align:right;font-size:10pt;}
         .note {color:gray;font-size:12pt;margin-top:20pt;}
         .hint {font-size:12pt;}
         .answer { background-color:bisque; border:dotted;border-width:1px}"#
-                .to_string()
-                + &tone_colours.css(),
-        );
+            .to_string()
+            + &tone_colours.css();
 
-        let deck = Deck::new(1234, deck_name, "");
+        NoteType {
+            fields,
+            templates,
+            css,
+            tts,
+            style,
+            romanisations: romanisations.to_vec(),
+            sort_key,
+        }
+    }
 
-        Anki { model, deck }
+    /// How readings are written on the notes.
+    pub fn style(&self) -> ReadingStyle {
+        self.style
     }
 
-    pub fn add_note(&mut self, word: &Word, mp3_file: Option<&str>, example: Option<&str>) {
-        let all_definitions = Self::to_all_definitions(word);
-        let all_definitions_with_pinyin = Self::to_all_definitions_with_pinyin(word);
-        let colour_hanzi = Self::to_colour_hanzi(word);
-        let mut fields = vec![
-            &all_definitions,
-            &all_definitions_with_pinyin,
-            &word.simplified,
-            &colour_hanzi,
-            example.unwrap_or(""),
-        ];
+    /// The note type's ID. Anki takes a note type with the same ID as one it
+    /// has to be that one, so this changes with the fields and templates.
+    fn id(&self) -> i64 {
+        let mut layout = self.fields.join("\x1f");
+        for template in &self.templates {
+            layout += &format!(
+                "\x1e{}\x1f{}\x1f{}",
+                template.name, template.front, template.back
+            );
+        }
+        id_for(&layout)
+    }
+
+    /// The note type's name, e.g. "Simple Model 1a2b3c4d", which like its ID
+    /// differs for each set of fields and templates.
+    pub fn name(&self) -> String {
+        format!("Simple Model {:08x}", self.id() >> 31)
+    }
 
-        let sound_field = mp3_file.map(|f| format!("[sound:{f}]"));
+    fn model(&self) -> Model {
+        let fields = self.fields.iter().map(|f| Field::new(f)).collect();
+        let templates = self
+            .templates
+            .iter()
+            .map(|t| Template::new(&t.name).qfmt(&t.front).afmt(&t.back))
+            .collect();
+        Model::new(self.id(), &self.name(), fields, templates).css(&self.css)
+    }
 
-        if let Some(f) = sound_field.as_ref() {
-            fields.push(f);
+    /// The value of each of `fields` for a card.
+    pub fn note_fields(&self, card: &Card) -> Vec<String> {
+        let word = card.word;
+        let mut fields = vec![
+            Self::to_all_definitions(word),
+            self.to_all_definitions_with_pinyin(word),
+            word.simplified.clone(),
+            Self::to_colour_hanzi(word),
+            card.example.unwrap_or("").to_string(),
+            Self::to_numbered_pinyin(word),
+            card.count.to_string(),
+        ];
+        fields.extend(
+            self.romanisations
+                .iter()
+                .map(|r| self.to_romanisation(word, *r)),
+        );
+        if self.sort_key {
+            // Zero-padded so that it sorts as text too
+            fields.push(format!("{:06}", card.position));
         }
 
-        self.deck
-            .add_note(Note::new(self.model.clone(), fields).unwrap());
+        // Notes still get (blank) sound fields if their audio couldn't be fetched
+        if self.tts {
+            fields.push(self.to_sound_field(word, &card.sounds));
+            fields.push(
+                card.example_sound
+                    .map(|f| format!("[sound:{f}]"))
+                    .unwrap_or_default(),
+            );
+        }
+        fields
     }
 
     fn colourise(token: &str, tone: Option<Tone>) -> String {
@@ -192,10 +332,35 @@ impl Anki {
         }
     }
 
-    pub fn write_to_file(&self, file: &str, media_files: Vec<&str>) {
-        let mut my_package =
-            genanki_rs::Package::new(vec![self.deck.clone()], media_files).unwrap();
-        my_package.write_to_file(file).unwrap();
+    fn to_sound_field(&self, word: &Word, mp3_files: &[(Option<&PinYin>, &str)]) -> String {
+        match mp3_files {
+            [(_, f)] => format!("[sound:{f}]"),
+            _ => mp3_files
+                .iter()
+                .map(|(py, f)| match py {
+                    // Label each reading's audio with the reading itself
+                    Some(py) => format!(
+                        "<div class=reading>{} [sound:{f}]</div>",
+                        py.colourise(&word.simplified, self.style)
+                    ),
+                    None => format!("[sound:{f}]"),
+                })
+                .collect::<Vec<_>>()
+                .join(""),
+        }
+    }
+
+    fn field_name(romanisation: &Romanisation) -> String {
+        romanisation.name().replace('-', "")
+    }
+
+    /// Every reading of the word, e.g. "hsing² / hang²" in Wade-Giles for 行
+    fn to_romanisation(&self, word: &Word, romanisation: Romanisation) -> String {
+        word.pinyins
+            .keys()
+            .map(|py| py.romanise(romanisation, self.style.spacing))
+            .collect::<Vec<_>>()
+            .join(" / ")
     }
 
     fn to_all_definitions(word: &Word) -> String {
@@ -211,13 +376,13 @@ impl Anki {
             .join("")
     }
 
-    fn to_all_definitions_with_pinyin(word: &Word) -> String {
+    fn to_all_definitions_with_pinyin(&self, word: &Word) -> String {
         word.pinyins
             .iter()
             .map(|(py, defs)| {
                 format!(
                     "<div class=reading>{}</div><div>{}</div>",
-                    py.colourise(),
+                    py.colourise(&word.simplified, self.style),
                     defs.iter().cloned().collect::<Vec<_>>().join(" · ")
                 )
             })
@@ -225,6 +390,16 @@ impl Anki {
             .join("")
     }
 
+    /// The numbered pinyin to type in for this word, or empty if the word has
+    /// more than one reading.
+    fn to_numbered_pinyin(word: &Word) -> String {
+        if word.pinyins.len() == 1 {
+            word.pinyins.keys().next().unwrap().to_numbered()
+        } else {
+            String::new()
+        }
+    }
+
     pub fn to_colour_hanzi(word: &Word) -> String {
         let tones_consensus = word
             .pinyins
@@ -249,3 +424,202 @@ impl Anki {
         }
     }
 }
+
+/// A stable ID for a deck or note type, from its name or layout.
+fn id_for(text: &str) -> i64 {
+    let hash = Sha256::digest(text.as_bytes());
+    i64::from_be_bytes(hash[..8].try_into().unwrap()) & i64::MAX
+}
+
+pub struct Anki {
+    note_type: NoteType,
+    model: Model,
+    deck_name: String,
+    /// The deck, followed by any sub-decks, by name
+    decks: Vec<(String, Deck)>,
+}
+
+impl Anki {
+    pub fn new(deck_name: &str, description: &str, note_type: NoteType) -> Self {
+        Anki {
+            model: note_type.model(),
+            deck_name: deck_name.to_string(),
+            decks: vec![(
+                deck_name.to_string(),
+                Deck::new(id_for(deck_name), deck_name, description),
+            )],
+            note_type,
+        }
+    }
+
+    /// The full name of `subdeck`, e.g. "ST04::Chapter 3", or of the deck
+    /// itself if None.
+    pub fn deck_name(deck_name: &str, subdeck: Option<&str>) -> String {
+        match subdeck {
+            Some(subdeck) => format!("{deck_name}::{subdeck}"),
+            None => deck_name.to_string(),
+        }
+    }
+
+    /// The deck or sub-deck to add notes to, creating it if need be.
+    fn deck(&mut self, subdeck: Option<&str>) -> &mut Deck {
+        let name = Self::deck_name(&self.deck_name, subdeck);
+        let index = match self.decks.iter().position(|(n, _)| *n == name) {
+            Some(index) => index,
+            None => {
+                // Decks are told apart by their IDs, so each needs its own
+                self.decks
+                    .push((name.clone(), Deck::new(id_for(&name), &name, "")));
+                self.decks.len() - 1
+            }
+        };
+        &mut self.decks[index].1
+    }
+
+    fn add_note(&mut self, card: &Card) {
+        let fields = self.note_type.note_fields(card);
+        let note = Note::new_with_options(
+            self.model.clone(),
+            fields.iter().map(|f| &f[..]).collect(),
+            None,
+            Some(card.tags.iter().map(|t| &t[..]).collect()),
+            None,
+        )
+        .unwrap();
+        self.deck(card.subdeck.as_deref()).add_note(note);
+    }
+
+    /// Add a note from an existing deck, keeping its GUID so that Anki updates
+    /// the note rather than adding a copy of it. Any fields it doesn't have are
+    /// left blank.
+    pub fn add_existing_note(&mut self, note: &ExistingNote) {
+        let fields = self
+            .note_type
+            .fields
+            .iter()
+            .map(|field| note.fields.get(field).map_or("", |value| &value[..]))
+            .collect();
+        let tags = note.tags.iter().map(|tag| &tag[..]).collect();
+        let note = Note::new_with_options(
+            self.model.clone(),
+            fields,
+            None,
+            Some(tags),
+            Some(&note.guid),
+        )
+        .unwrap();
+        self.deck(None).add_note(note);
+    }
+}
+
+impl Exporter for Anki {
+    fn add_card(&mut self, card: &Card) {
+        self.add_note(card);
+    }
+
+    fn write_to_file(&self, file: &str, media_files: Vec<&str>) -> anyhow::Result<()> {
+        let decks = self.decks.iter().map(|(_, deck)| deck.clone()).collect();
+        let mut my_package = genanki_rs::Package::new(decks, media_files)?;
+        my_package.write_to_file(file)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::export::fixtures::{bangzhu, card};
+    use std::collections::{BTreeSet, HashMap};
+
+    fn note_type(sides: &[Side], tts: bool) -> NoteType {
+        NoteType::new(
+            &ToneColours::Off,
+            sides,
+            tts,
+            ReadingStyle::default(),
+            &[],
+            false,
+        )
+    }
+
+    #[test]
+    fn test_model_id_and_name() {
+        let plain = note_type(&[], false);
+        let with_sound = note_type(&[], true);
+        assert_eq!(plain.id(), note_type(&[], false).id());
+        assert_eq!(plain.name(), note_type(&[], false).name());
+        assert_ne!(plain.id(), with_sound.id());
+        assert_ne!(plain.name(), with_sound.name());
+        assert_ne!(plain.id(), note_type(&[Side::TypePinyin], false).id());
+        assert!(plain.name().starts_with("Simple Model "));
+    }
+
+    #[test]
+    fn test_type_pinyin_fallback() {
+        let names = |note_type: &NoteType| -> Vec<String> {
+            note_type.templates.iter().map(|t| t.name.clone()).collect()
+        };
+        let type_pinyin = note_type(&[Side::TypePinyin], false);
+        assert_eq!(names(&type_pinyin), ["Card 3", "Card 2"]);
+        assert_eq!(
+            type_pinyin.templates[1].front,
+            "{{^NumberedPinyin}}<div class=chinese>{{Hanzi}}</div>{{/NumberedPinyin}}"
+        );
+        let both = note_type(&[Side::CeToEn, Side::TypePinyin], false);
+        assert_eq!(names(&both), ["Card 2", "Card 3"]);
+    }
+
+    /// The Sound and ExampleSound fields for a card.
+    fn sound_fields(card: &Card) -> Vec<String> {
+        let note_type = note_type(&[], true);
+        let fields = note_type.note_fields(card);
+        ["Sound", "ExampleSound"]
+            .iter()
+            .map(|name| {
+                let i = note_type.fields.iter().position(|f| f == name).unwrap();
+                fields[i].clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sound_fields() {
+        let bangzhu = bangzhu();
+        let with_sounds = Card {
+            sounds: vec![(None, "帮助.mp3")],
+            example_sound: Some("example.mp3"),
+            ..card(&bangzhu)
+        };
+        assert_eq!(
+            sound_fields(&with_sounds),
+            ["[sound:帮助.mp3]", "[sound:example.mp3]"]
+        );
+
+        // Notes without audio, such as when it couldn't be fetched, get blank fields
+        assert_eq!(sound_fields(&card(&bangzhu)), ["", ""]);
+
+        // Each reading's audio is labelled with the reading
+        let (xing, hang): (PinYin, PinYin) = ("xing2".parse().unwrap(), "hang2".parse().unwrap());
+        let word = Word {
+            simplified: "行".into(),
+            pinyins: HashMap::from([
+                (xing.clone(), BTreeSet::from(["to walk".into()])),
+                (hang.clone(), BTreeSet::from(["row".into()])),
+            ]),
+        };
+        let with_readings = Card {
+            sounds: vec![(Some(&xing), "行-xing2.mp3"), (Some(&hang), "行-hang2.mp3")],
+            ..card(&word)
+        };
+        assert_eq!(
+            sound_fields(&with_readings),
+            [
+                concat!(
+                    r#"<div class=reading><span class="tone2">xíng</span> [sound:行-xing2.mp3]</div>"#,
+                    r#"<div class=reading><span class="tone2">háng</span> [sound:行-hang2.mp3]</div>"#,
+                ),
+                ""
+            ]
+        );
+    }
+}
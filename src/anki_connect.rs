@@ -0,0 +1,370 @@
+use anyhow::{bail, ensure, Context};
+use reqwest::header::CONTENT_TYPE;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::anki::{Anki, NoteType};
+use crate::export::Card;
+
+/// Adds notes straight to a running Anki, through the AnkiConnect add-on
+/// (https://foosoft.net/projects/anki-connect/).
+pub struct AnkiConnect {
+    client: reqwest::Client,
+    url: String,
+    deck_name: String,
+    note_type: NoteType,
+    notes: Vec<PendingNote>,
+}
+
+/// A note to add to Anki.
+struct PendingNote {
+    hanzi: String,
+    /// The full name of the deck to add it to
+    deck: String,
+    fields: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// Which notes were added to Anki, by their hanzi.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PushReport {
+    pub added: Vec<String>,
+    /// Notes that weren't added because Anki already has them (or, as
+    /// AnkiConnect can't tell these apart, couldn't add them for another reason)
+    pub already_present: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    result: Value,
+    error: Option<String>,
+}
+
+impl AnkiConnect {
+    pub const DEFAULT_URL: &'static str = "http://localhost:8765";
+
+    pub fn new(url: &str, deck_name: &str, note_type: NoteType) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+            deck_name: deck_name.to_string(),
+            note_type,
+            notes: vec![],
+        }
+    }
+
+    pub fn add_card(&mut self, card: &Card) {
+        self.notes.push(PendingNote {
+            hanzi: card.word.simplified.clone(),
+            deck: Anki::deck_name(&self.deck_name, card.subdeck.as_deref()),
+            fields: self.note_type.note_fields(card),
+            tags: card.tags.clone(),
+        });
+    }
+
+    async fn request(&self, action: &str, params: Value) -> anyhow::Result<Value> {
+        let body = json!({ "action": action, "version": 6, "params": params });
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .with_context(|| format!("Connecting to AnkiConnect at {}", self.url))
+            .context("Is Anki open, with AnkiConnect installed?")?
+            .text()
+            .await
+            .with_context(|| format!("Getting the response to {action}"))?;
+        let response: Response = serde_json::from_str(&response)
+            .with_context(|| format!("Parsing the response to {action}: {response}"))?;
+        match response.error {
+            Some(error) => bail!("AnkiConnect {action} failed: {error}"),
+            None => Ok(response.result),
+        }
+    }
+
+    /// Create the note type and deck if Anki doesn't have them, upload
+    /// `media_files`, then add every note that Anki doesn't already have.
+    pub async fn push(&self, media_files: &[&str]) -> anyhow::Result<PushReport> {
+        let model_name = self.note_type.name();
+        let model_names: Vec<String> =
+            serde_json::from_value(self.request("modelNames", json!({})).await?)?;
+        if model_names.contains(&model_name) {
+            let fields: Vec<String> = serde_json::from_value(
+                self.request("modelFieldNames", json!({ "modelName": model_name }))
+                    .await?,
+            )?;
+            let missing: Vec<_> = self
+                .note_type
+                .fields
+                .iter()
+                .filter(|field| !fields.contains(field))
+                .map(|field| &field[..])
+                .collect();
+            ensure!(
+                missing.is_empty(),
+                "The '{}' note type in Anki doesn't have the fields {}, which these options need",
+                model_name,
+                missing.join(", ")
+            );
+        } else {
+            let templates: Vec<_> = self
+                .note_type
+                .templates
+                .iter()
+                .map(|t| json!({ "Name": t.name, "Front": t.front, "Back": t.back }))
+                .collect();
+            self.request(
+                "createModel",
+                json!({
+                    "modelName": model_name,
+                    "inOrderFields": self.note_type.fields,
+                    "css": self.note_type.css,
+                    "isCloze": false,
+                    "cardTemplates": templates,
+                }),
+            )
+            .await?;
+        }
+        // Does nothing if the deck already exists, and creates any parent decks
+        let mut decks = vec![&self.deck_name];
+        for note in &self.notes {
+            if !decks.contains(&&note.deck) {
+                decks.push(&note.deck);
+            }
+        }
+        for deck in decks {
+            self.request("createDeck", json!({ "deck": deck })).await?;
+        }
+
+        for file in media_files {
+            let path = Path::new(file);
+            let absolute = path
+                .canonicalize()
+                .with_context(|| format!("Finding {file}"))?;
+            self.request(
+                "storeMediaFile",
+                json!({
+                    "filename": path.file_name().and_then(|f| f.to_str()),
+                    "path": absolute,
+                }),
+            )
+            .await?;
+        }
+
+        let notes: Vec<_> = self
+            .notes
+            .iter()
+            .map(|note| {
+                let fields: serde_json::Map<_, _> = self
+                    .note_type
+                    .fields
+                    .iter()
+                    .cloned()
+                    .zip(note.fields.iter().map(|v| json!(v)))
+                    .collect();
+                json!({
+                    "deckName": note.deck,
+                    "modelName": model_name,
+                    "fields": fields,
+                    "options": { "allowDuplicate": false },
+                    "tags": note.tags,
+                })
+            })
+            .collect();
+
+        // addNotes fails outright if any note is a duplicate, so only give it
+        // the notes that can be added
+        let can_add: Vec<bool> = serde_json::from_value(
+            self.request("canAddNotes", json!({ "notes": notes }))
+                .await?,
+        )?;
+        let mut report = PushReport::default();
+        let mut to_add = vec![];
+        for ((pending, note), can_add) in self.notes.iter().zip(notes).zip(can_add) {
+            if can_add {
+                to_add.push((&pending.hanzi, note));
+            } else {
+                report.already_present.push(pending.hanzi.clone());
+            }
+        }
+        if to_add.is_empty() {
+            return Ok(report);
+        }
+
+        let (hanzi, notes): (Vec<_>, Vec<_>) = to_add.into_iter().unzip();
+        let ids: Vec<Option<u64>> =
+            serde_json::from_value(self.request("addNotes", json!({ "notes": notes })).await?)?;
+        for (hanzi, id) in hanzi.into_iter().zip(ids) {
+            match id {
+                Some(_) => report.added.push(hanzi.clone()),
+                None => report.already_present.push(hanzi.clone()),
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::anki::ToneColours;
+    use crate::export::fixtures::{bangzhu, card, word};
+    use crate::pinyin::ReadingStyle;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Answer one HTTP connection's AnkiConnect requests with `respond`,
+    /// recording each request's body in `requests`.
+    async fn serve(
+        mut stream: TcpStream,
+        respond: fn(&Value) -> Value,
+        requests: Arc<Mutex<Vec<Value>>>,
+    ) {
+        let mut buffer = vec![];
+        loop {
+            let Some(header_end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") else {
+                let mut chunk = [0; 4096];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                }
+                continue;
+            };
+            let headers = String::from_utf8_lossy(&buffer[..header_end]).to_lowercase();
+            let length: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            let body_start = header_end + 4;
+            while buffer.len() < body_start + length {
+                let mut chunk = [0; 4096];
+                let n = stream.read(&mut chunk).await.unwrap();
+                buffer.extend_from_slice(&chunk[..n]);
+            }
+            let request: Value =
+                serde_json::from_slice(&buffer[body_start..body_start + length]).unwrap();
+            buffer.drain(..body_start + length);
+
+            let body = json!({ "result": respond(&request), "error": null }).to_string();
+            requests.lock().unwrap().push(request);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    /// Start a mock AnkiConnect server, returning its URL and the requests
+    /// made to it.
+    async fn mock_anki_connect(respond: fn(&Value) -> Value) -> (String, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let served = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, respond, served.clone()));
+            }
+        });
+        (url, requests)
+    }
+
+    fn note_type() -> NoteType {
+        NoteType::new(
+            &ToneColours::Off,
+            &[],
+            false,
+            ReadingStyle::default(),
+            &[],
+            false,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_push() {
+        // Anki has neither the note type nor the deck, and already has 你好
+        let (url, requests) = mock_anki_connect(|request| match request["action"].as_str() {
+            Some("modelNames") => json!(["Basic"]),
+            Some("createDeck") => json!(1),
+            Some("storeMediaFile") => request["params"]["filename"].clone(),
+            Some("canAddNotes") => request["params"]["notes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|note| note["fields"]["Hanzi"] != "你好")
+                .collect(),
+            Some("addNotes") => json!([1700000000000u64]),
+            _ => Value::Null,
+        })
+        .await;
+
+        let (nihao, bangzhu) = (word("你好", "ni3 hao3", &["hello"]), bangzhu());
+        let mut anki_connect = AnkiConnect::new(&url, "ST04", note_type());
+        anki_connect.add_card(&card(&nihao));
+        anki_connect.add_card(&Card {
+            subdeck: Some("Chapter 2".into()),
+            ..card(&bangzhu)
+        });
+
+        let dir = TempDir::new().unwrap();
+        let media = dir.path().join("帮助.mp3");
+        std::fs::write(&media, b"ID3").unwrap();
+        let report = anki_connect
+            .push(&[&media.display().to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            PushReport {
+                added: vec!["帮助".into()],
+                already_present: vec!["你好".into()],
+            }
+        );
+
+        let requests = requests.lock().unwrap();
+        let actions: Vec<_> = requests.iter().map(|r| &r["action"]).collect();
+        assert_eq!(
+            actions,
+            [
+                "modelNames",
+                "createModel",
+                "createDeck",
+                "createDeck",
+                "storeMediaFile",
+                "canAddNotes",
+                "addNotes"
+            ]
+        );
+        assert_eq!(requests[1]["params"]["modelName"], note_type().name());
+        assert_eq!(requests[1]["params"]["inOrderFields"][2], "Hanzi");
+        assert_eq!(requests[2]["params"]["deck"], "ST04");
+        assert_eq!(requests[3]["params"]["deck"], "ST04::Chapter 2");
+        assert_eq!(requests[4]["params"]["filename"], "帮助.mp3");
+        let added = &requests[6]["params"]["notes"];
+        assert_eq!(added.as_array().unwrap().len(), 1);
+        assert_eq!(added[0]["fields"]["Hanzi"], "帮助");
+        assert_eq!(added[0]["deckName"], "ST04::Chapter 2");
+        assert_eq!(added[0]["options"]["allowDuplicate"], false);
+        assert_eq!(added[0]["tags"][0], "hsk2");
+    }
+
+    #[tokio::test]
+    async fn test_push_to_mismatched_note_type() {
+        let (url, _) = mock_anki_connect(|request| match request["action"].as_str() {
+            Some("modelNames") => json!([note_type().name()]),
+            Some("modelFieldNames") => json!(["Front", "Back"]),
+            _ => Value::Null,
+        })
+        .await;
+
+        let anki_connect = AnkiConnect::new(&url, "ST04", note_type());
+        let error = anki_connect.push(&[]).await.unwrap_err();
+        assert!(error.to_string().contains("AllDefinitions"));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::anki::ToneColours;
+use crate::dict::CEDict;
+use crate::pinyin::ReadingStyle;
+
+const CSS: &str = r#"
+body { font-family: "PingFang SC", "Noto Sans CJK SC", sans-serif; font-size: 24px; line-height: 2.5; max-width: 40em; margin: auto; }
+rt { font-size: 12px; }
+.word { position: relative; cursor: pointer; }
+.definition { display: none; position: absolute; left: 0; top: 100%; z-index: 1; width: 16em; padding: 4px 8px; font-size: 14px; line-height: 1.4; background-color: white; border: 1px solid grey; }
+.word:hover .definition, .word:focus .definition { display: block; }
+"#;
+
+/// Make an HTML page of the text split into `tokens`, with the reading of each
+/// word over it and its definitions shown on hover (or tap). Only words for
+/// which `should_annotate` is true are annotated.
+pub fn annotate(
+    tokens: &[&str],
+    dict: &CEDict,
+    tone_colours: &ToneColours,
+    style: ReadingStyle,
+    should_annotate: impl Fn(&str) -> bool,
+) -> String {
+    let mut body = String::new();
+    for token in tokens {
+        if !cjk::is_simplified_chinese(token) || !should_annotate(token) {
+            body += &escape_html(token).replace('\n', "<br>\n");
+            continue;
+        }
+        for word in dict.get(token) {
+            let readings = word
+                .pinyins
+                .keys()
+                .map(|py| py.colourise(&word.simplified, style))
+                .collect::<Vec<_>>()
+                .join(" / ");
+            let definitions = word
+                .pinyins
+                .values()
+                .map(|defs| escape_html(&defs.iter().cloned().collect::<Vec<_>>().join(" · ")))
+                .collect::<Vec<_>>()
+                .join("<br>");
+            body += &format!(
+                "<span class=word tabindex=0><ruby>{}<rt>{readings}</rt></ruby><span class=definition>{definitions}</span></span>",
+                escape_html(&word.simplified)
+            );
+        }
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=utf-8>\n<style>{CSS}{}</style>\n</head>\n<body>\n{body}\n</body>\n</html>\n",
+        tone_colours.css()
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dict::Word;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_annotate() {
+        let bangzhu = Word {
+            simplified: "帮助".into(),
+            pinyins: HashMap::from([(
+                "bang1 zhu4".parse().unwrap(),
+                ["assistance".into(), "to help".into()].into(),
+            )]),
+        };
+        let dict = CEDict {
+            dict: HashMap::from([("帮助".to_string(), bangzhu)]),
+        };
+        let html = annotate(
+            &["你好", "，", "帮助", "<b>"],
+            &dict,
+            &ToneColours::Off,
+            ReadingStyle::default(),
+            |word| word != "你好",
+        );
+        assert!(html.contains("<style>"));
+        assert!(html.contains(".tone1 {color: black;}"));
+        assert!(html.contains(
+            r#"<ruby>帮助<rt><span class="tone1">bāng</span><span class="tone4">zhù</span></rt></ruby>"#
+        ));
+        assert!(html.contains("<span class=definition>assistance · to help</span></span>"));
+        assert!(html.contains("\n你好，<span class=word"));
+        assert!(html.contains("&lt;b&gt;"));
+    }
+}
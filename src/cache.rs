@@ -0,0 +1,123 @@
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::dict::PinYin;
+use crate::tts::TtsBackend;
+
+/// A directory of generated audio, keyed by a hash of the text spoken and the
+/// backend settings used to speak it, so that audio is only ever fetched once.
+pub struct AudioCache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl AudioCache {
+    /// Use `dir` if given, or otherwise a directory under the user's cache dir.
+    pub fn new(dir: Option<PathBuf>) -> anyhow::Result<Self> {
+        let dir = match dir {
+            Some(d) => d,
+            None => dirs::cache_dir()
+                .context("Couldn't find a cache directory, specify one with --cache-dir")?
+                .join("zw_to_anki")
+                .join("audio"),
+        };
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the audio for `text` (read as `reading`) spoken by `backend`
+    /// lives, or will live.
+    pub fn path_for(
+        &self,
+        backend: &impl TtsBackend,
+        text: &str,
+        reading: Option<&PinYin>,
+    ) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(backend.cache_key());
+        hasher.update([0]);
+        hasher.update(text);
+        if let Some(reading) = reading {
+            hasher.update([0]);
+            hasher.update(reading.to_numbered());
+        }
+        let hash = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        self.dir.join(format!("{hash}.{}", backend.extension()))
+    }
+
+    pub fn stats(&self) -> anyhow::Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        for file in self.files()? {
+            stats.files += 1;
+            stats.bytes += file.metadata()?.len();
+        }
+        Ok(stats)
+    }
+
+    /// Delete everything in the cache, returning what was deleted.
+    pub fn clean(&self) -> anyhow::Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        for file in self.files()? {
+            stats.files += 1;
+            stats.bytes += file.metadata()?.len();
+            std::fs::remove_file(file.path())
+                .with_context(|| format!("Removing {}", file.path().display()))?;
+        }
+        Ok(stats)
+    }
+
+    fn files(&self) -> anyhow::Result<Vec<std::fs::DirEntry>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Reading {}", self.dir.display()))?
+            .filter(|entry| entry.as_ref().map_or(true, |e| e.path().is_file()))
+            .collect::<Result<_, _>>()
+            .context("Reading cache entry")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tts::TtsEngine;
+
+    #[test]
+    fn test_path_depends_on_backend_settings() {
+        let cache = AudioCache::new(Some("cache".into())).unwrap();
+        let google = TtsEngine::default();
+        let slow_google = TtsEngine::default().with_speed("0.5".into());
+        let espeak: TtsEngine = "espeak-ng".parse().unwrap();
+
+        let path = cache.path_for(&google, "帮助", None);
+        assert_eq!(path, cache.path_for(&google, "帮助", None));
+        assert_eq!(path.extension().unwrap(), "mp3");
+        assert_ne!(path, cache.path_for(&google, "帮", None));
+        assert_ne!(path, cache.path_for(&slow_google, "帮助", None));
+        assert_eq!(
+            cache.path_for(&espeak, "帮助", None).extension().unwrap(),
+            "wav"
+        );
+
+        let xing = PinYin(vec!["xing2".into()]);
+        let hang = PinYin(vec!["hang2".into()]);
+        assert_ne!(
+            cache.path_for(&espeak, "行", Some(&xing)),
+            cache.path_for(&espeak, "行", Some(&hang))
+        );
+    }
+}
@@ -0,0 +1,72 @@
+/// Split `text` into chapters, each starting at a line that looks like a
+/// chapter heading (see [`is_heading`]). Any text before the first heading is
+/// part of the first chapter, and text without any headings is one chapter.
+pub fn split_chapters(text: &str) -> Vec<&str> {
+    let mut chapters = vec![];
+    let mut start = 0;
+    let mut offset = 0;
+    let mut seen_heading = false;
+    for line in text.split_inclusive('\n') {
+        if is_heading(line) {
+            // Only start a new chapter if the current one has something in it
+            if seen_heading && !text[start..offset].trim().is_empty() {
+                chapters.push(&text[start..offset]);
+                start = offset;
+            }
+            seen_heading = true;
+        }
+        offset += line.len();
+    }
+    chapters.push(&text[start..]);
+    chapters
+}
+
+/// Whether `line` is a chapter heading, like 第三章, 第12回 or "Chapter 3".
+pub fn is_heading(line: &str) -> bool {
+    let line = line.trim();
+    if line.chars().count() > 30 {
+        return false;
+    }
+    if let Some(rest) = line.strip_prefix('第') {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || "零〇一二两三四五六七八九十百千".contains(c)))
+            .unwrap_or(rest.len());
+        return number_len > 0
+            && rest[number_len..].starts_with(['章', '回', '节', '卷', '部', '篇']);
+    }
+    let mut words = line.split_whitespace();
+    words
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case("chapter"))
+        && words.next().is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_heading() {
+        assert!(is_heading("第三章"));
+        assert!(is_heading("  第十二回 大闹天宫\n"));
+        assert!(is_heading("第3章：开始"));
+        assert!(is_heading("Chapter 3"));
+        assert!(!is_heading("第一次见面的时候，他就帮助了我。"));
+        assert!(!is_heading("第章"));
+        assert!(!is_heading("Chapter"));
+    }
+
+    #[test]
+    fn test_split_chapters() {
+        assert_eq!(split_chapters("你好。\n帮助"), ["你好。\n帮助"]);
+        assert_eq!(
+            split_chapters("第一章\n你好。\n第二章\n帮助\n"),
+            ["第一章\n你好。\n", "第二章\n帮助\n"]
+        );
+        // A title before the first heading is part of the first chapter
+        assert_eq!(
+            split_chapters("西游记\n\n第一回\n你好。\n第二回\n帮助"),
+            ["西游记\n\n第一回\n你好。\n", "第二回\n帮助"]
+        );
+    }
+}
@@ -1,6 +1,10 @@
-use crate::pinyin::add_diacritic;
+use crate::pinyin::{
+    add_diacritic, join_pinyin, parse_pinyin, spoken_tones, to_romanisation, to_zhuyin, Phonetic,
+    ReadingStyle, Romanisation, Sandhi, Spacing,
+};
 use std::collections::{BTreeSet, HashMap};
 use std::convert::From;
+use std::str::FromStr;
 use std::vec::Vec;
 
 const CE_DICT: &str = include_str!("cedict_1_0_ts_utf-8_mdbg.txt");
@@ -43,12 +47,134 @@ impl From<Tone> for usize {
 pub struct PinYin(pub Vec<PinYinSyllable>);
 
 impl PinYin {
-    pub fn colourise(&self) -> String {
+    /// Write out the reading of `hanzi` with each syllable coloured by tone.
+    pub fn colourise(&self, hanzi: &str, style: ReadingStyle) -> String {
+        match style.phonetic {
+            Phonetic::Pinyin => join_pinyin(
+                &self.colourise_syllables(hanzi, style, add_diacritic),
+                style.spacing,
+            ),
+            Phonetic::Zhuyin => self
+                .colourise_syllables(hanzi, style, to_zhuyin)
+                .into_iter()
+                .map(|(_, zhuyin)| zhuyin)
+                .collect::<Vec<_>>()
+                .join(" "),
+            Phonetic::Both => format!(
+                "{} ({})",
+                self.colourise(
+                    hanzi,
+                    ReadingStyle {
+                        phonetic: Phonetic::Pinyin,
+                        ..style
+                    }
+                ),
+                self.colourise(
+                    hanzi,
+                    ReadingStyle {
+                        phonetic: Phonetic::Zhuyin,
+                        ..style
+                    }
+                )
+            ),
+        }
+    }
+
+    fn colourise_syllables(
+        &self,
+        hanzi: &str,
+        style: ReadingStyle,
+        write: fn(&str, Option<Tone>) -> String,
+    ) -> Vec<(PinYinSyllable, String)> {
+        let spoken_tones = match style.sandhi {
+            Sandhi::Off => self.0.iter().map(|pys| pys.tone).collect(),
+            Sandhi::Replace | Sandhi::Alongside => spoken_tones(hanzi, &self.0),
+        };
+        join_erhua(self.0.iter().cloned().zip(spoken_tones))
+            .into_iter()
+            .map(|(pys, spoken)| {
+                let written = |tone| write(&pys.text, tone);
+                let colour = if style.colour_spoken_tone {
+                    spoken
+                } else {
+                    pys.tone
+                };
+                let html = match style.sandhi {
+                    Sandhi::Replace => colourise(&written(spoken), colour),
+                    Sandhi::Alongside if spoken != pys.tone => format!(
+                        "{}<sup class=sandhi>{}</sup>",
+                        colourise(&written(pys.tone), colour),
+                        written(spoken)
+                    ),
+                    _ => colourise(&written(pys.tone), colour),
+                };
+                (pys, html)
+            })
+            .collect()
+    }
+
+    /// The reading as plain text in `romanisation`, e.g. "pang¹-chu⁴" in Wade-Giles.
+    /// Pinyin syllables are separated according to `spacing`, while other
+    /// romanisations have their own separators.
+    pub fn romanise(&self, romanisation: Romanisation, spacing: Spacing) -> String {
+        let written: Vec<_> = join_erhua(self.0.iter().cloned().map(|pys| (pys, ())))
+            .into_iter()
+            .map(|(pys, _)| {
+                let written = to_romanisation(&pys.text, pys.tone, romanisation);
+                (pys, written)
+            })
+            .collect();
+        if romanisation == Romanisation::Pinyin {
+            return join_pinyin(&written, spacing);
+        }
+
+        let mut romanised = String::new();
+        for (i, (pys, written_syllable)) in written.iter().enumerate() {
+            if i > 0 {
+                // Names like 亚历山大·杜布切克 are two words
+                if pys.text == "·" || written[i - 1].0.text == "·" {
+                    romanised.push(' ');
+                } else {
+                    romanised += romanisation.separator();
+                }
+            }
+            romanised += written_syllable;
+        }
+        romanised
+    }
+
+    /// Plain numbered pinyin, e.g. "bang1 zhu4", suitable for typing in.
+    pub fn to_numbered(&self) -> String {
         self.0
             .iter()
-            .map(|pys| colourise(&add_diacritic(&pys.text, pys.tone), pys.tone))
-            .collect::<String>()
+            .filter(|pys| pys.text != "·")
+            .map(PinYinSyllable::to_numbered)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl FromStr for PinYin {
+    type Err = String;
+
+    /// Parse tone-marked or numbered pinyin, see [`parse_pinyin`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_pinyin(s).map(Self)
+    }
+}
+
+/// Erhua is written as part of the syllable before it, e.g. 哪儿 is nǎr
+fn join_erhua<T>(
+    syllables: impl IntoIterator<Item = (PinYinSyllable, T)>,
+) -> Vec<(PinYinSyllable, T)> {
+    let mut joined: Vec<(PinYinSyllable, T)> = vec![];
+    for (pys, extra) in syllables {
+        match joined.last_mut() {
+            Some((previous, _)) if pys.is_erhua() => previous.text += &pys.text,
+            _ => joined.push((pys, extra)),
+        }
     }
+    joined
 }
 
 fn colourise(token: &str, tone: Option<Tone>) -> String {
@@ -64,19 +190,34 @@ pub struct PinYinSyllable {
     pub tone: Option<Tone>,
 }
 
+impl PinYinSyllable {
+    /// Whether this is the r of erhua, written as a separate syllable in CEDict.
+    pub fn is_erhua(&self) -> bool {
+        self.text.eq_ignore_ascii_case("r") && matches!(self.tone, None | Some(Tone::Fifth))
+    }
+
+    /// Lowercase, with ü written as v so that it can be typed on any keyboard.
+    pub fn to_numbered(&self) -> String {
+        format!(
+            "{}{}",
+            self.text.to_lowercase().replace('ü', "v"),
+            self.tone
+                .map(|t| usize::from(t).to_string())
+                .unwrap_or_default()
+        )
+    }
+}
+
 impl From<&str> for PinYinSyllable {
     fn from(value: &str) -> Self {
         // Parse from e.g.
         // 'yang3'
         // 'lu:4'
-        if value == "·" {
-            return Self {
-                text: value.to_string(),
-                tone: None,
-            };
-        }
-        let (text, tone_str) = value.split_at(value.len() - 1);
-        let tone = tone_str.parse::<u8>().ok().map(Tone::from);
+        // 'X' (as in X光)
+        let (text, tone) = match value.chars().last().and_then(|c| c.to_digit(10)) {
+            Some(tone) => (&value[..value.len() - 1], Some(Tone::from(tone as u8))),
+            None => (value, None),
+        };
         Self {
             // Note that ü is represented as 'u:' for some reason in the MDBG
             // dictionary, so fix that here.
@@ -94,6 +235,18 @@ pub struct Word {
     pub pinyins: HashMap<PinYin, BTreeSet<String>>,
 }
 
+impl Word {
+    /// Whether the word is only ever a name, like 北京 or 孔子, since CEDict
+    /// capitalises the pinyin of proper nouns.
+    pub fn is_proper_noun(&self) -> bool {
+        !self.pinyins.is_empty()
+            && self.pinyins.keys().all(|py| {
+                py.0.first()
+                    .is_some_and(|pys| pys.text.starts_with(char::is_uppercase))
+            })
+    }
+}
+
 pub struct CEDict {
     pub dict: HashMap<String, Word>,
 }
@@ -164,6 +317,14 @@ impl CEDict {
         }
     }
 
+    /// Whether `get` can find `word`, either as a whole or character by
+    /// character.
+    pub fn contains(&self, word: &str) -> bool {
+        !word.is_empty()
+            && (self.dict.contains_key(word)
+                || word.chars().all(|c| self.dict.contains_key(&c.to_string())))
+    }
+
     /// Get all readings of a word.
     /// If the word is not in the dictionary, break it down to chunks and try
     /// to find the best chunking of the word that _is_ in the dictionary.
@@ -275,6 +436,18 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_proper_noun() {
+        assert!(CEDict::parse_line("北京 北京 [Bei3 jing1] /Beijing/").is_proper_noun());
+        assert!(!CEDict::parse_line("帮助 帮助 [bang1 zhu4] /assistance/").is_proper_noun());
+
+        // 张 is a surname, but also a measure word
+        let mut zhang = CEDict::parse_line("張 张 [Zhang1] /surname Zhang/");
+        let measure_word = CEDict::parse_line("張 张 [zhang1] /classifier for flat objects/");
+        zhang.pinyins.extend(measure_word.pinyins);
+        assert!(!zhang.is_proper_noun());
+    }
+
     #[test]
     fn test_parse_name() {
         let word = CEDict::parse_line("亞歷山大·杜布切克 亚历山大·杜布切克 [Ya4 li4 shan1 da4 · Du4 bu4 qie1 ke4] /Alexander Dubček (1921-1992), leader of Czechoslovakia (1968-1969)/");
@@ -322,6 +495,214 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_numbered_pinyin() {
+        for (line, expected) in [
+            ("幫助 帮助 [bang1 zhu4] /to help/", "bang1 zhu4"),
+            ("綠 绿 [lu:4] /green/", "lv4"),
+            ("西安 西安 [Xi1 an1] /Xi'an/", "xi1 an1"),
+            (
+                "亞歷山大·杜布切克 亚历山大·杜布切克 [Ya4 li4 shan1 da4 · Du4 bu4 qie1 ke4] /Alexander Dubček/",
+                "ya4 li4 shan1 da4 du4 bu4 qie1 ke4",
+            ),
+        ] {
+            let word = CEDict::parse_line(line);
+            assert_eq!(word.pinyins.keys().next().unwrap().to_numbered(), expected);
+        }
+    }
+
+    #[test]
+    fn test_colourise_sandhi() {
+        let buyao: PinYin = "bu4 yao4".parse().unwrap();
+        let style = |sandhi, colour_spoken_tone| ReadingStyle {
+            sandhi,
+            colour_spoken_tone,
+            ..ReadingStyle::default()
+        };
+        assert_eq!(
+            buyao.colourise("不要", style(Sandhi::Off, false)),
+            r#"<span class="tone4">bù</span><span class="tone4">yào</span>"#
+        );
+        assert_eq!(
+            buyao.colourise("不要", style(Sandhi::Replace, true)),
+            r#"<span class="tone2">bú</span><span class="tone4">yào</span>"#
+        );
+        assert_eq!(
+            buyao.colourise("不要", style(Sandhi::Replace, false)),
+            r#"<span class="tone4">bú</span><span class="tone4">yào</span>"#
+        );
+        assert_eq!(
+            buyao.colourise("不要", style(Sandhi::Alongside, false)),
+            r#"<span class="tone4">bù</span><sup class=sandhi>bú</sup><span class="tone4">yào</span>"#
+        );
+    }
+
+    #[test]
+    fn test_edge_case_syllables() {
+        for (line, expected) in [
+            ("哪兒 哪儿 [na3 r5] /where?/", "nǎr"),
+            ("嗯 嗯 [ng2] /(a groan or grunt)/", "n\u{301}g"),
+            ("呣 呣 [m2] /interjection/", "m\u{301}"),
+            ("噷 噷 [hm5] /(interjection)/", "hm"),
+            ("X光 X光 [X guang1] /X-ray/", "Xguāng"),
+            ("𠮷 𠮷 [xx5] /variant/", "xx"),
+        ] {
+            let word = CEDict::parse_line(line);
+            let pinyin = word.pinyins.keys().next().unwrap();
+            let style = ReadingStyle::default();
+            assert_eq!(
+                strip_tags(&pinyin.colourise(&word.simplified, style)),
+                expected
+            );
+        }
+    }
+
+    /// Every entry should be readable in every style, without panicking.
+    #[test]
+    fn test_render_all_entries() {
+        let dict = CEDict::new();
+        for word in dict.dict.values() {
+            for pinyin in word.pinyins.keys() {
+                for phonetic in [Phonetic::Pinyin, Phonetic::Zhuyin, Phonetic::Both] {
+                    for sandhi in [Sandhi::Off, Sandhi::Replace, Sandhi::Alongside] {
+                        let style = ReadingStyle {
+                            phonetic,
+                            sandhi,
+                            colour_spoken_tone: true,
+                            spacing: Spacing::Spaced,
+                        };
+                        pinyin.colourise(&word.simplified, style);
+                    }
+                }
+                pinyin.to_numbered();
+            }
+        }
+    }
+
+    #[test]
+    fn test_apostrophes() {
+        for (line, spacing, expected) in [
+            ("西安 西安 [Xi1 an1] /Xi'an/", Spacing::Joined, "Xī'ān"),
+            ("先 先 [xian1] /first/", Spacing::Joined, "xiān"),
+            ("方案 方案 [fang1 an4] /plan/", Spacing::Joined, "fāng'àn"),
+            ("哪兒 哪儿 [na3 r5] /where?/", Spacing::Joined, "nǎr"),
+            (
+                "帮助 帮助 [bang1 zhu4] /to help/",
+                Spacing::Spaced,
+                "bāng zhù",
+            ),
+            ("西安 西安 [Xi1 an1] /Xi'an/", Spacing::Spaced, "Xī ān"),
+            ("哪兒 哪儿 [na3 r5] /where?/", Spacing::Spaced, "nǎr"),
+        ] {
+            let word = CEDict::parse_line(line);
+            let pinyin = word.pinyins.keys().next().unwrap();
+            let style = ReadingStyle {
+                spacing,
+                ..ReadingStyle::default()
+            };
+            assert_eq!(
+                strip_tags(&pinyin.colourise(&word.simplified, style)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_romanise() {
+        for (line, expected) in [
+            (
+                "幫助 帮助 [bang1 zhu4] /to help/",
+                ["bāngzhù", "ㄅㄤ ㄓㄨˋ", "pang¹-chu⁴", "bāngjù", "pɑŋ˥.ʈʂu˥˩"],
+            ),
+            (
+                "西安 西安 [Xi1 an1] /Xi'an/",
+                ["Xī'ān", "ㄒㄧ ㄢ", "Hsi¹-an¹", "Syīān", "ɕi˥.an˥"],
+            ),
+            (
+                "哪兒 哪儿 [na3 r5] /where?/",
+                ["nǎr", "ㄋㄚˇㄦ", "nar³", "nǎr", "na˞˨˩˦"],
+            ),
+            (
+                "亞歷山大·杜布切克 亚历山大·杜布切克 [Ya4 li4 shan1 da4 · Du4 bu4 qie1 ke4] /Alexander Dubček/",
+                [
+                    "Yàlìshāndà·Dùbùqiēkè",
+                    "ㄧㄚˋ ㄌㄧˋ ㄕㄢ ㄉㄚˋ · ㄉㄨˋ ㄅㄨˋ ㄑㄧㄝ ㄎㄜˋ",
+                    "Ya⁴-li⁴-shan¹-ta⁴ · Tu⁴-pu⁴-ch'ieh¹-k'o⁴",
+                    "Yàlìshāndà · Dùbùchyēkè",
+                    "ja˥˩.li˥˩.ʂan˥.ta˥˩ · tu˥˩.pu˥˩.tɕʰjɛ˥.kʰɤ˥˩",
+                ],
+            ),
+        ] {
+            let word = CEDict::parse_line(line);
+            let pinyin = word.pinyins.keys().next().unwrap();
+            for (romanisation, expected) in [
+                Romanisation::Pinyin,
+                Romanisation::Zhuyin,
+                Romanisation::WadeGiles,
+                Romanisation::Yale,
+                Romanisation::Ipa,
+            ]
+            .into_iter()
+            .zip(expected)
+            {
+                assert_eq!(
+                    pinyin.romanise(romanisation, Spacing::Joined),
+                    expected,
+                    "{romanisation:?}"
+                );
+            }
+        }
+
+        let word = CEDict::parse_line("西安 西安 [Xi1 an1] /Xi'an/");
+        let pinyin = word.pinyins.keys().next().unwrap();
+        assert_eq!(
+            pinyin.romanise(Romanisation::Pinyin, Spacing::Spaced),
+            "Xī ān"
+        );
+        assert_eq!(
+            pinyin.romanise(Romanisation::WadeGiles, Spacing::Spaced),
+            "Hsi¹-an¹"
+        );
+    }
+
+    fn strip_tags(html: &str) -> String {
+        let mut in_tag = false;
+        html.chars()
+            .filter(|c| {
+                match c {
+                    '<' => in_tag = true,
+                    '>' => {
+                        in_tag = false;
+                        return false;
+                    }
+                    _ => {}
+                }
+                !in_tag
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_contains() {
+        let dict = CEDict {
+            dict: [
+                "帮 帮 [bang1] /to help/",
+                "帮助 帮助 [bang1 zhu4] /to help/",
+            ]
+            .into_iter()
+            .map(|line| {
+                let word = CEDict::parse_line(line);
+                (word.simplified.clone(), word)
+            })
+            .collect(),
+        };
+        assert!(dict.contains("帮助"));
+        assert!(dict.contains("帮帮"));
+        assert!(!dict.contains("助"));
+        assert!(!dict.contains("hello"));
+        assert!(!dict.contains(""));
+    }
+
     #[test]
     fn test_chunking() {
         assert_eq!(
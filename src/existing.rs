@@ -0,0 +1,295 @@
+use anyhow::{bail, Context};
+use rusqlite::{Connection, OpenFlags};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tempfile::{NamedTempFile, TempDir};
+
+/// A note from an existing Anki deck or collection.
+pub struct ExistingNote {
+    pub guid: String,
+    /// The value of each of the note's fields, by name
+    pub fields: HashMap<String, String>,
+    pub tags: Vec<String>,
+}
+
+/// The notes in an existing Anki deck (.apkg) or collection (.anki2), so that
+/// words which already have cards can be skipped, or the notes merged into a
+/// new deck.
+pub struct ExistingNotes {
+    notes: Vec<ExistingNote>,
+    hanzi: HashSet<String>,
+    /// The package's media files, unpacked
+    media: Option<TempDir>,
+}
+
+#[derive(Deserialize)]
+struct NoteTypeJson {
+    flds: Vec<FieldJson>,
+}
+
+#[derive(Deserialize)]
+struct FieldJson {
+    name: String,
+    ord: usize,
+}
+
+impl ExistingNotes {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let is_package = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("apkg"));
+        if is_package {
+            Self::from_package(path)
+        } else {
+            Self::from_collection(path)
+        }
+        .with_context(|| format!("Reading notes from {}", path.display()))
+    }
+
+    fn from_package(path: &Path) -> anyhow::Result<Self> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let has_file = |name: &str| archive.file_names().any(|n| n == name);
+        // Packages from newer versions of Anki may also have a collection.anki2,
+        // but it only holds a note asking to upgrade Anki
+        let name = if has_file("collection.anki21") {
+            "collection.anki21"
+        } else if has_file("collection.anki21b") {
+            bail!("Only newer versions of Anki can read this deck - export it again with \"Support older Anki versions\" ticked");
+        } else if has_file("collection.anki2") {
+            "collection.anki2"
+        } else {
+            bail!("There's no collection in this deck");
+        };
+        let mut collection = NamedTempFile::new()?;
+        std::io::copy(&mut archive.by_name(name)?, &mut collection)?;
+        let mut existing = Self::from_collection(collection.path())?;
+
+        // Media files are stored by number, with a `media` file mapping each
+        // number to its name
+        let media: HashMap<String, String> = match archive.by_name("media") {
+            Ok(file) => serde_json::from_reader(file).context("Reading the media list")?,
+            Err(zip::result::ZipError::FileNotFound) => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let dir = TempDir::new()?;
+        for (number, name) in media {
+            let Some(file_name) = Path::new(&name).file_name() else {
+                continue;
+            };
+            let mut file = File::create(dir.path().join(file_name))?;
+            std::io::copy(&mut archive.by_name(&number)?, &mut file)
+                .with_context(|| format!("Unpacking {name}"))?;
+        }
+        existing.media = Some(dir);
+        Ok(existing)
+    }
+
+    fn from_collection(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let note_types = Self::note_types(&connection).context("Reading note types")?;
+
+        let mut statement = connection.prepare("SELECT guid, mid, flds, tags FROM notes")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        let mut notes = vec![];
+        for row in rows {
+            let (guid, note_type, values, tags) = row?;
+            let Some(note_type) = note_types.get(&note_type) else {
+                continue;
+            };
+            let values: Vec<_> = values.split('\x1f').collect();
+            let fields = note_type
+                .iter()
+                .filter_map(|field| Some((field.name.clone(), values.get(field.ord)?.to_string())))
+                .collect();
+            notes.push(ExistingNote {
+                guid,
+                fields,
+                tags: tags.split_whitespace().map(String::from).collect(),
+            });
+        }
+
+        let hanzi = notes
+            .iter()
+            .filter_map(|note| Some(note.fields.get("Hanzi")?.trim().to_string()))
+            .collect();
+        Ok(Self {
+            notes,
+            hanzi,
+            media: None,
+        })
+    }
+
+    /// The fields of each note type, by its ID. Older collections keep note
+    /// types as JSON in `col.models`, while newer ones leave that empty and
+    /// have `notetypes` and `fields` tables instead.
+    fn note_types(connection: &Connection) -> anyhow::Result<HashMap<i64, Vec<FieldJson>>> {
+        let models: String =
+            connection.query_row("SELECT models FROM col", [], |row| row.get(0))?;
+        if !models.trim().is_empty() {
+            let models: HashMap<String, NoteTypeJson> = serde_json::from_str(&models)?;
+            if !models.is_empty() {
+                return models
+                    .into_iter()
+                    .map(|(id, note_type)| Ok((id.parse()?, note_type.flds)))
+                    .collect();
+            }
+        }
+
+        let mut note_types: HashMap<i64, Vec<FieldJson>> = HashMap::new();
+        let mut statement = connection.prepare("SELECT ntid, ord, name FROM fields")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                FieldJson {
+                    ord: row.get(1)?,
+                    name: row.get(2)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (note_type, field) = row?;
+            note_types.entry(note_type).or_default().push(field);
+        }
+        Ok(note_types)
+    }
+
+    /// Whether there's already a note for `word`.
+    pub fn contains(&self, word: &str) -> bool {
+        self.hanzi.contains(word)
+    }
+
+    /// The notes with a Hanzi field, like those this tool makes. Notes of
+    /// other types are left out, as they won't fit this tool's note type.
+    pub fn notes(&self) -> impl Iterator<Item = &ExistingNote> {
+        self.notes
+            .iter()
+            .filter(|note| note.fields.contains_key("Hanzi"))
+    }
+
+    /// The media files from the package, such as audio for the notes.
+    pub fn media_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let Some(dir) = &self.media else {
+            return Ok(vec![]);
+        };
+        let mut files = vec![];
+        for entry in std::fs::read_dir(dir.path())? {
+            files.push(entry?.path());
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    /// Write a collection with notes of the given Hanzi (of this tool's note
+    /// type) and a note of Anki's Basic type. Modern collections have their
+    /// note types in tables rather than as JSON.
+    fn write_collection(path: &Path, hanzi: &[&str], modern: bool) {
+        let connection = Connection::open(path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE col (models TEXT);
+                 CREATE TABLE notes (guid TEXT, mid INTEGER, flds TEXT, tags TEXT);",
+            )
+            .unwrap();
+        if modern {
+            connection
+                .execute_batch(
+                    "INSERT INTO col VALUES ('');
+                     CREATE TABLE notetypes (id INTEGER PRIMARY KEY, name TEXT);
+                     CREATE TABLE fields (ntid INTEGER, ord INTEGER, name TEXT, config BLOB);
+                     INSERT INTO notetypes VALUES (1607392319, 'Simple Model'), (1342697561, 'Basic');
+                     INSERT INTO fields VALUES
+                         (1607392319, 0, 'AllDefinitions', x''),
+                         (1607392319, 1, 'Hanzi', x''),
+                         (1342697561, 0, 'Front', x''),
+                         (1342697561, 1, 'Back', x'');",
+                )
+                .unwrap();
+        } else {
+            let note_types = r#"{
+                "1607392319": {"flds": [{"name": "AllDefinitions", "ord": 0}, {"name": "Hanzi", "ord": 1}]},
+                "1342697561": {"flds": [{"name": "Front", "ord": 0}, {"name": "Back", "ord": 1}]}
+            }"#;
+            connection
+                .execute("INSERT INTO col VALUES (?1)", [note_types])
+                .unwrap();
+        }
+        for (i, hanzi) in hanzi.iter().enumerate() {
+            connection
+                .execute(
+                    "INSERT INTO notes VALUES (?1, 1607392319, ?2, ' ST04 hsk2 ')",
+                    [format!("guid{i}"), format!("to help\x1f{hanzi}")],
+                )
+                .unwrap();
+        }
+        connection
+            .execute(
+                "INSERT INTO notes VALUES ('basic', 1342697561, '谢谢\x1fthanks', '')",
+                [],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_from_collection() {
+        for modern in [false, true] {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("collection.anki2");
+            write_collection(&path, &["帮助", "行"], modern);
+
+            let existing = ExistingNotes::from_file(&path).unwrap();
+            assert!(existing.contains("帮助"));
+            assert!(existing.contains("行"));
+            assert!(!existing.contains("谢谢"));
+
+            let notes: Vec<_> = existing.notes().collect();
+            assert_eq!(notes.len(), 2);
+            assert_eq!(notes[0].guid, "guid0");
+            assert_eq!(notes[0].fields["AllDefinitions"], "to help");
+            assert_eq!(notes[0].tags, ["ST04", "hsk2"]);
+            assert!(existing.media_files().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_from_package() {
+        let dir = TempDir::new().unwrap();
+        let collection = dir.path().join("collection.anki2");
+        write_collection(&collection, &["帮助"], true);
+
+        let path = dir.path().join("ST04.apkg");
+        let mut package = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        package.start_file("collection.anki2", options).unwrap();
+        package
+            .write_all(&std::fs::read(&collection).unwrap())
+            .unwrap();
+        package.start_file("media", options).unwrap();
+        package
+            .write_all(r#"{"0": "帮助.mp3"}"#.as_bytes())
+            .unwrap();
+        package.start_file("0", options).unwrap();
+        package.write_all(b"ID3").unwrap();
+        package.finish().unwrap();
+
+        let existing = ExistingNotes::from_file(&path).unwrap();
+        assert!(existing.contains("帮助"));
+        let media = existing.media_files().unwrap();
+        assert_eq!(media.len(), 1);
+        assert!(media[0].ends_with("帮助.mp3"));
+        assert_eq!(std::fs::read(&media[0]).unwrap(), b"ID3");
+    }
+}
@@ -0,0 +1,385 @@
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::dict::{PinYin, Word};
+use crate::pinyin::{Romanisation, Spacing};
+
+/// Everything that goes on the card for one word.
+pub struct Card<'a> {
+    /// Where the card comes in the deck
+    pub position: usize,
+    pub word: &'a Word,
+    /// How many times the word appears in the text
+    pub count: usize,
+    pub example: Option<&'a str>,
+    /// The word's audio, with one file per reading if they were spoken separately
+    pub sounds: Vec<(Option<&'a PinYin>, &'a str)>,
+    pub example_sound: Option<&'a str>,
+    /// Tags for the note, such as its HSK level
+    pub tags: Vec<String>,
+    /// The sub-deck to put the card in, e.g. "Chapter 3"
+    pub subdeck: Option<String>,
+}
+
+/// Somewhere to write cards to, such as an Anki deck.
+pub trait Exporter {
+    fn add_card(&mut self, card: &Card);
+
+    /// Write out every card added, along with `media_files` if the format
+    /// can hold them.
+    fn write_to_file(&self, file: &str, media_files: Vec<&str>) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// An Anki deck
+    Apkg,
+    /// Tab-separated hanzi, pinyin, definitions, example and count, which Anki, Pleco and
+    /// Quizlet can all import
+    Tsv,
+    /// Comma-separated, like tsv
+    Csv,
+    /// A JSON array of words, with their readings, definitions, example, count and tags
+    Json,
+    /// A Pleco flashcard import file, with the words in a category named after the deck
+    Pleco,
+}
+
+impl Format {
+    /// Guess the format from the extension of the output file.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        Self::from_str(&extension, true).ok()
+    }
+}
+
+/// Cards as rows of text, with a column separator of `separator`, and pinyin
+/// written with `spacing`.
+pub struct DelimitedExporter {
+    separator: char,
+    spacing: Spacing,
+    rows: Vec<[String; 5]>,
+}
+
+impl DelimitedExporter {
+    pub fn tsv(spacing: Spacing) -> Self {
+        Self {
+            separator: '\t',
+            spacing,
+            rows: vec![],
+        }
+    }
+
+    pub fn csv(spacing: Spacing) -> Self {
+        Self {
+            separator: ',',
+            spacing,
+            rows: vec![],
+        }
+    }
+
+    fn escape(&self, value: &str) -> String {
+        if self.separator == '\t' {
+            // TSV has no quoting, so there can't be tabs or newlines in a value
+            value.replace(['\t', '\n', '\r'], " ")
+        } else if value.contains([self.separator, '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+impl Exporter for DelimitedExporter {
+    fn add_card(&mut self, card: &Card) {
+        let (readings, definitions): (Vec<_>, Vec<_>) = card
+            .word
+            .pinyins
+            .iter()
+            .map(|(py, defs)| {
+                (
+                    py.romanise(Romanisation::Pinyin, self.spacing),
+                    defs.iter().cloned().collect::<Vec<_>>().join("; "),
+                )
+            })
+            .unzip();
+        self.rows.push([
+            card.word.simplified.clone(),
+            readings.join(" / "),
+            definitions.join(" / "),
+            card.example.unwrap_or_default().to_string(),
+            card.count.to_string(),
+        ]);
+    }
+
+    fn write_to_file(&self, file: &str, _media_files: Vec<&str>) -> anyhow::Result<()> {
+        let contents: String = self
+            .rows
+            .iter()
+            .map(|row| {
+                let row: Vec<_> = row.iter().map(|value| self.escape(value)).collect();
+                row.join(&self.separator.to_string()) + "\n"
+            })
+            .collect();
+        std::fs::write(file, contents).with_context(|| format!("Writing {file}"))
+    }
+}
+
+/// Cards as a Pleco flashcard import file: a `//Category` line, then a line
+/// of hanzi, numbered pinyin and definitions for each reading of each word.
+/// Cards in sub-decks go in sub-categories, like `//ST04/Chapter 3`.
+pub struct PlecoExporter {
+    category: String,
+    /// The lines in each category, in the order the categories were first used
+    categories: Vec<(String, Vec<String>)>,
+}
+
+impl PlecoExporter {
+    pub fn new(category: &str) -> Self {
+        Self {
+            category: Self::escape(category),
+            categories: vec![],
+        }
+    }
+
+    fn escape(value: &str) -> String {
+        value.replace(['\t', '\n', '\r'], " ")
+    }
+}
+
+impl Exporter for PlecoExporter {
+    fn add_card(&mut self, card: &Card) {
+        let category = match &card.subdeck {
+            Some(subdeck) => format!("{}/{}", self.category, Self::escape(subdeck)),
+            None => self.category.clone(),
+        };
+        let lines = match self.categories.iter().position(|(c, _)| *c == category) {
+            Some(index) => &mut self.categories[index].1,
+            None => {
+                self.categories.push((category, vec![]));
+                &mut self.categories.last_mut().unwrap().1
+            }
+        };
+        // Pleco matches cards to its own dictionary by their reading, so each
+        // reading gets its own card
+        for (py, defs) in &card.word.pinyins {
+            lines.push(format!(
+                "{}\t{}\t{}\n",
+                Self::escape(&card.word.simplified),
+                py.to_numbered(),
+                Self::escape(&defs.iter().cloned().collect::<Vec<_>>().join("; ")),
+            ));
+        }
+    }
+
+    fn write_to_file(&self, file: &str, _media_files: Vec<&str>) -> anyhow::Result<()> {
+        let contents: String = if self.categories.is_empty() {
+            format!("//{}\n", self.category)
+        } else {
+            self.categories
+                .iter()
+                .map(|(category, lines)| format!("//{category}\n{}", lines.concat()))
+                .collect()
+        };
+        std::fs::write(file, contents).with_context(|| format!("Writing {file}"))
+    }
+}
+
+#[derive(Serialize)]
+struct JsonWord {
+    hanzi: String,
+    readings: Vec<JsonReading>,
+    example: Option<String>,
+    count: usize,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JsonReading {
+    pinyin: String,
+    numbered_pinyin: String,
+    definitions: Vec<String>,
+}
+
+/// Cards as a JSON array, with pinyin written with `spacing`.
+pub struct JsonExporter {
+    spacing: Spacing,
+    words: Vec<JsonWord>,
+}
+
+impl JsonExporter {
+    pub fn new(spacing: Spacing) -> Self {
+        Self {
+            spacing,
+            words: vec![],
+        }
+    }
+}
+
+impl Exporter for JsonExporter {
+    fn add_card(&mut self, card: &Card) {
+        self.words.push(JsonWord {
+            hanzi: card.word.simplified.clone(),
+            readings: card
+                .word
+                .pinyins
+                .iter()
+                .map(|(py, defs)| JsonReading {
+                    pinyin: py.romanise(Romanisation::Pinyin, self.spacing),
+                    numbered_pinyin: py.to_numbered(),
+                    definitions: defs.iter().cloned().collect(),
+                })
+                .collect(),
+            example: card.example.map(String::from),
+            count: card.count,
+            tags: card.tags.clone(),
+        });
+    }
+
+    fn write_to_file(&self, file: &str, _media_files: Vec<&str>) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.words)?;
+        std::fs::write(file, contents).with_context(|| format!("Writing {file}"))
+    }
+}
+
+/// A word and card for testing exporters with.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::Card;
+    use crate::dict::Word;
+    use std::collections::HashMap;
+
+    /// A word with a single reading.
+    pub(crate) fn word(simplified: &str, pinyin: &str, definitions: &[&str]) -> Word {
+        Word {
+            simplified: simplified.into(),
+            pinyins: HashMap::from([(
+                pinyin.parse().unwrap(),
+                definitions.iter().map(|d| d.to_string()).collect(),
+            )]),
+        }
+    }
+
+    pub(crate) fn bangzhu() -> Word {
+        word("帮助", "bang1 zhu4", &["assistance", "to help"])
+    }
+
+    /// A card for `word`, with an example that needs quoting in CSV.
+    pub(crate) fn card(word: &Word) -> Card<'_> {
+        Card {
+            position: 0,
+            word,
+            count: 2,
+            example: Some("请帮助我, \"朋友\""),
+            sounds: vec![],
+            example_sound: None,
+            tags: vec!["hsk2".into()],
+            subdeck: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::fixtures::{bangzhu, card};
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("ST04.apkg"), Some(Format::Apkg));
+        assert_eq!(Format::from_path("out/ST04.TSV"), Some(Format::Tsv));
+        assert_eq!(Format::from_path("ST04.json"), Some(Format::Json));
+        assert_eq!(Format::from_path("ST04"), None);
+        assert_eq!(Format::from_path("ST04.txt"), None);
+    }
+
+    #[test]
+    fn test_delimited() {
+        let word = &bangzhu();
+        let dir = TempDir::new().unwrap();
+
+        let mut tsv = DelimitedExporter::tsv(Spacing::Joined);
+        tsv.add_card(&card(word));
+        let path = dir.path().join("words.tsv").display().to_string();
+        tsv.write_to_file(&path, vec![]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "帮助\tbāngzhù\tassistance; to help\t请帮助我, \"朋友\"\t2\n"
+        );
+
+        let mut csv = DelimitedExporter::csv(Spacing::Joined);
+        csv.add_card(&card(word));
+        let path = dir.path().join("words.csv").display().to_string();
+        csv.write_to_file(&path, vec![]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "帮助,bāngzhù,assistance; to help,\"请帮助我, \"\"朋友\"\"\",2\n"
+        );
+    }
+
+    #[test]
+    fn test_spaced_pinyin() {
+        let word = &bangzhu();
+        let dir = TempDir::new().unwrap();
+
+        let mut tsv = DelimitedExporter::tsv(Spacing::Spaced);
+        tsv.add_card(&card(word));
+        let path = dir.path().join("words.tsv").display().to_string();
+        tsv.write_to_file(&path, vec![]).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("帮助\tbāng zhù\t"));
+
+        let mut json = JsonExporter::new(Spacing::Spaced);
+        json.add_card(&card(word));
+        let path = dir.path().join("words.json");
+        json.write_to_file(&path.display().to_string(), vec![])
+            .unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written[0]["readings"][0]["pinyin"], "bāng zhù");
+    }
+
+    #[test]
+    fn test_pleco() {
+        let word = &bangzhu();
+        let mut pleco = PlecoExporter::new("ST04");
+        pleco.add_card(&card(word));
+        pleco.add_card(&Card {
+            subdeck: Some("Chapter 2".into()),
+            ..card(word)
+        });
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("pleco.txt");
+        pleco
+            .write_to_file(&path.display().to_string(), vec![])
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "//ST04\n帮助\tbang1 zhu4\tassistance; to help\n\
+             //ST04/Chapter 2\n帮助\tbang1 zhu4\tassistance; to help\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let mut json = JsonExporter::new(Spacing::Joined);
+        json.add_card(&card(&bangzhu()));
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("words.json");
+        json.write_to_file(&path.display().to_string(), vec![])
+            .unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written[0]["hanzi"], "帮助");
+        assert_eq!(written[0]["readings"][0]["pinyin"], "bāngzhù");
+        assert_eq!(written[0]["readings"][0]["numbered_pinyin"], "bang1 zhu4");
+        assert_eq!(written[0]["readings"][0]["definitions"][1], "to help");
+        assert_eq!(written[0]["count"], 2);
+        assert_eq!(written[0]["tags"][0], "hsk2");
+    }
+}
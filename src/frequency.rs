@@ -0,0 +1,71 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// How common words are in general, from a word frequency list such as
+/// SUBTLEX-CH.
+pub struct FrequencyList {
+    /// The position of each word in the list, most frequent first, from 1
+    ranks: HashMap<String, usize>,
+}
+
+impl FrequencyList {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading frequency list {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Parsing frequency list {}", path.display()))
+    }
+
+    /// Parse lines of a word followed by its count, separated by whitespace,
+    /// with any further columns ignored. Lines whose second column isn't a
+    /// number, like SUBTLEX-CH's headers, are skipped.
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut counts = vec![];
+        for line in contents.lines() {
+            let mut columns = line.split_whitespace();
+            let (Some(word), Some(count)) = (columns.next(), columns.next()) else {
+                continue;
+            };
+            if let Ok(count) = count.parse::<f64>() {
+                counts.push((word, count));
+            }
+        }
+        anyhow::ensure!(!counts.is_empty(), "Expected lines of a word and its count");
+
+        counts.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        let mut ranks = HashMap::new();
+        for (rank, (word, _)) in counts.into_iter().enumerate() {
+            ranks.entry(word.to_string()).or_insert(rank + 1);
+        }
+        Ok(Self { ranks })
+    }
+
+    /// Where `word` comes in the list, with 1 being the most frequent, or None
+    /// if it isn't in the list at all.
+    pub fn rank(&self, word: &str) -> Option<usize> {
+        self.ranks.get(word).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let list = FrequencyList::parse(
+            "Total word count: 33,546,516\n\
+             Word\tWCount\tW/million\n\
+             我\t50147\t1494.87\n\
+             的\t1\t0.03\n\
+             你\t50377\t1501.73\n",
+        )
+        .unwrap();
+        assert_eq!(list.rank("你"), Some(1));
+        assert_eq!(list.rank("我"), Some(2));
+        assert_eq!(list.rank("的"), Some(3));
+        assert_eq!(list.rank("帮助"), None);
+
+        assert!(FrequencyList::parse("Word\tWCount\n").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::dict::{PinYin, Word};
+
+/// Words the user already knows, so don't need cards for.
+pub struct KnownWords {
+    /// Each word, with the readings it's known in, or None if it's known in
+    /// all of them
+    words: HashMap<String, Option<Vec<PinYin>>>,
+}
+
+impl KnownWords {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading known words {}", path.display()))?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Parse a word per line, optionally followed by a tone-marked or numbered
+    /// reading (e.g. '行 xíng' or '行 xing2'), in which case only that reading
+    /// of the word is known. Anything else after the word (such as its
+    /// definition, as in an exported word list) is ignored, as are blank lines
+    /// and lines starting with '#'.
+    pub fn parse(contents: &str) -> Self {
+        let mut words: HashMap<String, Option<Vec<PinYin>>> = HashMap::new();
+        for line in contents.lines().filter(|line| !line.starts_with('#')) {
+            let mut tokens = line.split_whitespace();
+            let Some(word) = tokens.next() else {
+                continue;
+            };
+            let reading = Self::reading(word, &tokens.collect::<Vec<_>>());
+            let readings = words
+                .entry(word.to_string())
+                .or_insert_with(|| Some(vec![]));
+            match (readings, reading) {
+                (Some(readings), Some(reading)) => readings.push(reading),
+                (readings, None) => *readings = None,
+                _ => {}
+            }
+        }
+        Self { words }
+    }
+
+    /// The reading of `word` at the start of the rest of its line, if there
+    /// is one: the most of `rest` that's pinyin with tones, with no more
+    /// syllables than the word has characters.
+    fn reading(word: &str, rest: &[&str]) -> Option<PinYin> {
+        let characters = word.chars().count();
+        (1..=rest.len())
+            .map_while(|n| {
+                rest[..n]
+                    .join(" ")
+                    .parse::<PinYin>()
+                    .ok()
+                    .filter(|py| py.0.len() <= characters)
+            })
+            .last()
+            .filter(|py| py.0.iter().any(|pys| pys.tone.is_some()))
+    }
+
+    /// Whether `word` is known in any reading.
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains_key(word)
+    }
+
+    /// Whether `word` is known in every one of its readings.
+    pub fn knows(&self, word: &Word) -> bool {
+        match self.words.get(&word.simplified) {
+            None => false,
+            Some(None) => true,
+            Some(Some(readings)) => word
+                .pinyins
+                .keys()
+                .all(|py| readings.iter().any(|known| same_reading(known, py))),
+        }
+    }
+}
+
+/// Whether two readings are the same, ignoring the capitalisation CEDict uses
+/// for names.
+fn same_reading(a: &PinYin, b: &PinYin) -> bool {
+    a.0.len() == b.0.len()
+        && a.0
+            .iter()
+            .zip(&b.0)
+            .all(|(a, b)| a.text.eq_ignore_ascii_case(&b.text) && a.tone == b.tone)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let known = KnownWords::parse("# My words\n你好\tni3 hao3\n\n帮助 bāngzhù to help\n");
+        assert!(known.contains("你好"));
+        assert!(known.contains("帮助"));
+        assert!(!known.contains("好"));
+        assert!(!known.contains("#"));
+    }
+
+    #[test]
+    fn test_readings() {
+        let xing = Word {
+            simplified: "行".into(),
+            pinyins: HashMap::from([
+                ("xing2".parse().unwrap(), ["to walk".into()].into()),
+                ("hang2".parse().unwrap(), ["row".into()].into()),
+            ]),
+        };
+        let xian = Word {
+            simplified: "西安".into(),
+            pinyins: HashMap::from([("Xi1 an1".parse().unwrap(), ["Xi'an".into()].into())]),
+        };
+
+        // Only one reading of 行 is known, so it still needs a card
+        let known = KnownWords::parse(
+            "行 xíng to walk
+西安 xī'ān
+",
+        );
+        assert!(known.contains("行"));
+        assert!(!known.knows(&xing));
+        assert!(known.knows(&xian));
+
+        let known = KnownWords::parse(
+            "行 xing2
+行\thang2\trow
+",
+        );
+        assert!(known.knows(&xing));
+
+        // Without tones, what follows the word isn't taken as its reading
+        let known = KnownWords::parse(
+            "行 a line
+西安 xi an
+",
+        );
+        assert!(known.knows(&xing));
+        assert!(known.knows(&xian));
+
+        let known = KnownWords::parse(
+            "西安 xi1 an4
+",
+        );
+        assert!(!known.knows(&xian));
+    }
+}
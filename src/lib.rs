@@ -1,4 +1,15 @@
 pub mod anki;
+pub mod anki_connect;
+pub mod annotate;
+pub mod cache;
+pub mod chapter;
 pub mod dict;
+pub mod existing;
+pub mod export;
+pub mod frequency;
+pub mod known;
 pub mod pinyin;
+pub mod pipeline;
+pub mod preview;
+pub mod report;
 pub mod tts;
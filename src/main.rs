@@ -1,163 +1,562 @@
-use clap::Parser;
-use futures::future;
-use hsk::Hsk;
-use jieba_rs::Jieba;
-use std::collections::{HashMap, HashSet};
+use anyhow::{bail, Context};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
 use std::fs::read_to_string;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use zw_to_anki::anki::{Anki, Side, ToneColours};
+use zw_to_anki::anki::{NoteType, Side, ToneColours};
+use zw_to_anki::anki_connect::AnkiConnect;
+use zw_to_anki::annotate::annotate;
+use zw_to_anki::cache::AudioCache;
 use zw_to_anki::dict::CEDict;
-use zw_to_anki::tts;
+use zw_to_anki::existing::ExistingNotes;
+use zw_to_anki::export::Format;
+use zw_to_anki::frequency::FrequencyList;
+use zw_to_anki::known::KnownWords;
+use zw_to_anki::pinyin::{Phonetic, ReadingStyle, Romanisation, Sandhi, Spacing};
+use zw_to_anki::pipeline::{DeckBuilder, DeckOptions, Order, OutputOptions, SplitBy, TtsSettings};
+use zw_to_anki::report::Report;
+use zw_to_anki::tts::{TtsEngine, TtsOptions};
 
 /// Chunk up chinese text and make an Anki deck
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// File to be converted to flashcards
-    #[arg(short, long, conflicts_with = "text")]
+    #[arg(short, long, global = true, conflicts_with = "text")]
     file: Option<PathBuf>,
 
     /// Text to be converted to flashcards
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     text: Option<String>,
 
-    /// Output '.apkg' Anki deck path
+    /// Output path: an '.apkg' Anki deck, or a '.tsv', '.csv' or '.json' word list, or a Pleco
+    /// flashcard file (see --format)
     #[arg(short, long)]
     output: Option<String>,
 
-    /// Optionally, an HSK level. Words that are in HSK at or below this level will not be added to the deck.
+    /// Optionally, the format to write --output in, if it isn't clear from its extension
+    #[arg(value_enum, long)]
+    format: Option<Format>,
+
+    /// Optionally, the name of the deck (or Pleco category). Defaults to the name of --output
+    /// without its extension, e.g. 'ST04' for 'decks/ST04.apkg'. Use '::' for a sub-deck, e.g.
+    /// 'Books::ST04'.
     #[arg(long)]
+    deck_name: Option<String>,
+
+    /// Add the notes straight to Anki, which must be open with the AnkiConnect add-on installed.
+    /// Optionally, the URL AnkiConnect is listening on.
+    #[arg(long, value_name = "URL", num_args = 0..=1, default_missing_value = AnkiConnect::DEFAULT_URL)]
+    anki_connect: Option<String>,
+
+    /// Optionally, a directory to write a preview of the cards to: an 'index.html' page showing
+    /// the front and back of each card as Anki would, with any audio from --tts
+    #[arg(long, value_name = "DIR")]
+    preview: Option<String>,
+
+    /// Optionally, an HSK level. Words that are in HSK at or below this level will not be added to the deck.
+    #[arg(long, global = true)]
     hsk_filter: Option<u8>,
 
-    /// Optionally: either "off" to turn tone colours off, or five semicolon-separated RGB colour codes for the five tones. For example, '00e304;b35815;f00f0f;1767fe;777777' (the default).
+    /// Optionally, a file of words that are already known, one per line, optionally followed by
+    /// a reading such as 'xíng' or 'xing2' if only that reading is known (anything else after the
+    /// word is ignored). These will not be added to the deck.
+    #[arg(long, global = true)]
+    known_words: Option<PathBuf>,
+
+    /// Optionally, an Anki deck ('.apkg', exported with "Support older Anki versions" ticked) or
+    /// collection ('.anki2') to skip the words of, as they already have cards
     #[arg(long)]
+    existing: Option<PathBuf>,
+
+    /// Write the notes from --existing (and their media) into the output deck too, so that it has
+    /// both the old and new words. The old notes keep their GUIDs, so Anki updates them rather
+    /// than adding them again.
+    #[arg(long, requires = "existing")]
+    merge_existing: bool,
+
+    /// Only make cards for words that appear in the text at least this many times
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    min_count: usize,
+
+    /// Optionally, a word frequency list such as SUBTLEX-CH, with a word and its count on each
+    /// line, for use with --skip-top and --skip-below
+    #[arg(long)]
+    frequency_list: Option<PathBuf>,
+
+    /// Optionally, skip words that are among the N most frequent in --frequency-list, as being
+    /// too common to need a card
+    #[arg(long, value_name = "N", requires = "frequency_list")]
+    skip_top: Option<usize>,
+
+    /// Optionally, skip words that aren't among the N most frequent in --frequency-list, as being
+    /// too rare to be worth learning
+    #[arg(long, value_name = "N", requires = "frequency_list")]
+    skip_below: Option<usize>,
+
+    /// Optionally: either "off" to turn tone colours off, or five semicolon-separated RGB colour codes for the five tones. For example, '00e304;b35815;f00f0f;1767fe;777777' (the default).
+    #[arg(long, global = true)]
     tone_colours: Option<ToneColours>,
 
-    /// Optionally: either 'ce-to-en' to produce only cards that test Chinese to English, or
-    /// 'en-to-ce' for the opposite.
-    #[arg(value_enum, short, long)]
-    side: Option<Side>,
+    /// Optionally: a comma-separated list of the cards to produce - 'ce-to-en' for cards that test
+    /// Chinese to English, 'en-to-ce' for the opposite, and 'type-pinyin' for cards where the
+    /// numbered pinyin (e.g. 'bang1 zhu4') must be typed in, which words with several readings get
+    /// a 'ce-to-en' card in place of. Defaults to 'ce-to-en,en-to-ce'.
+    #[arg(value_enum, short, long, value_delimiter = ',')]
+    side: Vec<Side>,
+
+    /// How to write readings: as tone-marked pinyin, zhuyin (bopomofo), or both
+    #[arg(value_enum, long, global = true, default_value_t = Phonetic::Pinyin)]
+    phonetic: Phonetic,
 
-    /// Add Chinese audio to each flashcard
+    /// Whether to show tones as they're spoken, e.g. 你好 as níhǎo rather than nǐhǎo: 'off' for
+    /// dictionary tones, 'replace' for spoken tones, or 'alongside' for dictionary tones with the
+    /// spoken tone after any syllable where it differs
+    #[arg(value_enum, long, global = true, default_value_t = Sandhi::Off)]
+    sandhi: Sandhi,
+
+    /// Colour syllables by the tone they're spoken with (see --sandhi) rather than their
+    /// dictionary tone
+    #[arg(long, global = true)]
+    colour_spoken_tone: bool,
+
+    /// Whether to run the syllables of pinyin together, with an apostrophe where needed (e.g.
+    /// Xī'ān), or separate them with spaces (e.g. Xī ān)
+    #[arg(value_enum, long, global = true, default_value_t = Spacing::Joined)]
+    pinyin_spacing: Spacing,
+
+    /// Optionally, a comma-separated list of other ways to write readings, each added to cards as
+    /// an extra field: 'pinyin' and 'zhuyin' (without tone colours), 'wade-giles', 'yale' or 'ipa'
+    #[arg(value_enum, long, value_delimiter = ',')]
+    romanisation: Vec<Romanisation>,
+
+    /// Add Chinese audio to each flashcard, for both the word and its example
     #[arg(long)]
     tts: bool,
 
+    /// Optionally, the TTS backend to use with --tts: 'google' (the default), 'espeak-ng', 'piper',
+    /// or a command template such as 'edge-tts --text {text} --write-media {mp3}', where {text} is
+    /// replaced by the text to speak (or given on stdin if absent) and {wav} or {mp3} by the
+    /// output file. Commands that take {pinyin} (numbered pinyin) or {ssml} (SSML with a pinyin
+    /// phoneme hint) are told which reading to use, and words with several readings get audio
+    /// for each.
+    #[arg(long)]
+    tts_backend: Option<TtsEngine>,
+
+    /// The maximum number of TTS requests to have in flight at once
+    #[arg(long, default_value_t = TtsOptions::default().concurrency, value_parser = parse_concurrency)]
+    tts_concurrency: usize,
+
+    /// How many times to retry a failed TTS request, backing off exponentially
+    #[arg(long, default_value_t = TtsOptions::default().retries)]
+    tts_retries: u32,
+
+    /// Optionally, the maximum number of TTS requests to start per second
+    #[arg(long, value_name = "PER_SECOND", value_parser = parse_rate_limit)]
+    tts_rate_limit: Option<f64>,
+
+    /// Optionally, the voice for the TTS backend to use: a language code such as 'zh-TW' for
+    /// Google, or whatever {voice} should be replaced with for a command.
+    #[arg(long)]
+    tts_voice: Option<String>,
+
+    /// Optionally, the speed for the TTS backend to use, in the backend's own units (e.g. '0.5'
+    /// for slow Google audio).
+    #[arg(long)]
+    tts_speed: Option<String>,
+
+    /// Optionally, the directory to cache TTS audio in. Defaults to a directory under the user's
+    /// cache directory.
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// The order to add cards to the deck in, which is the order Anki will show new cards in
+    #[arg(value_enum, long, default_value_t = Order::FirstOccurrence)]
+    order: Order,
+
+    /// Optionally, tags to add to every note, on top of those added anyway: the name of --file
+    /// (or of the deck, for --text), the chapter the word first appears in (e.g. 'chapter-3') if
+    /// the text has chapters, the word's HSK level ('hsk1' to 'hsk6', or 'non-hsk') and
+    /// 'proper-noun' for names
+    #[arg(long, value_delimiter = ',')]
+    tag: Vec<String>,
+
+    /// How to split the deck into sub-decks: 'chapter' for one per chapter of the text (starting
+    /// at lines like '第三章' or 'Chapter 3'), by the chapter each word first appears in, 'hsk' for
+    /// one per HSK level, or 'none'
+    #[arg(value_enum, long, default_value_t = SplitBy::None)]
+    split_by: SplitBy,
+
+    /// Add a SortKey field to each note with its position in the deck (see --order), for sorting
+    /// by in Anki's browser
+    #[arg(long)]
+    sort_key: bool,
+
     /// Don't add an example to each flashcard
     #[arg(long)]
     no_example: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the readings and definitions of a word
+    Lookup {
+        /// The word to look up, e.g. 帮助
+        word: String,
+    },
+    /// Report on how hard the text is: how much of it each HSK level covers, how much of it is
+    /// known (see --known-words and --hsk-filter), and which words are unknown
+    Report {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the text as an HTML page with the reading of each word over it and its definitions
+    /// shown on hover (or tap), styled with --tone-colours, --phonetic and so on
+    Annotate {
+        /// Only annotate words that aren't known (see --known-words and --hsk-filter)
+        #[arg(long)]
+        unknown_only: bool,
+    },
+    /// Manage the cache of TTS audio
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// Show how much audio is cached
+    Stats,
+    /// Delete all cached audio
+    Clean,
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-
-    let to_chunk = match (args.file, args.text) {
-        (Some(f), None) => read_to_string(f).unwrap(),
-        (None, Some(t)) => t,
-        _ => panic!("Supply either file or sentence"),
-    };
-    let mut jieba = Jieba::new();
-    let dict = CEDict::new();
-    for word in dict.dict.keys() {
-        // Add in words from the MDBG set - we don't have frequency data for these but it still
-        // seems likely that jieba having a larger vocabulary will help it to correctly segmentise.
-        jieba.add_word(word, None, None);
+    if let Err(e) = run(args).await {
+        eprintln!("Error: {e:#}");
+        std::process::exit(1);
     }
-    let words: HashSet<_> = jieba.cut(&to_chunk, false).into_iter().collect();
-    let sentences: Vec<_> = to_chunk.split(|c| c == '\n' || c == '。').collect();
+}
 
-    let hsk_list = Hsk::new();
+async fn run(args: Args) -> anyhow::Result<()> {
+    let format = args.output.as_ref().map(|o| {
+        args.format
+            .or_else(|| Format::from_path(o))
+            .unwrap_or_else(|| {
+                Args::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!(
+                            "Can't tell what to write '{o}' as from its extension - use '.apkg', \
+                         '.tsv', '.csv' or '.json', or choose a format with --format"
+                        ),
+                    )
+                    .exit()
+            })
+    });
+    if args.merge_existing && format != Some(Format::Apkg) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--merge-existing needs an '.apkg' --output",
+            )
+            .exit();
+    }
 
-    if let Some(o) = args.output {
-        let mut words_for_cards = HashMap::new();
+    if let Some(Command::Lookup { word }) = &args.command {
+        let dict = CEDict::new();
+        if !dict.contains(word) {
+            bail!("'{word}' isn't in the dictionary");
+        }
+        lookup(&dict, word, args.pinyin_spacing);
+        return Ok(());
+    }
 
-        for word in words {
-            if !cjk::is_simplified_chinese(word) {
-                continue;
+    if let Some(Command::Cache { action }) = &args.command {
+        let cache = AudioCache::new(args.cache_dir)?;
+        match action {
+            CacheAction::Stats => {
+                let stats = cache.stats()?;
+                println!(
+                    "{} audio files ({} bytes) cached in {}",
+                    stats.files,
+                    stats.bytes,
+                    cache.dir().display()
+                );
             }
-
-            for result in dict.get(word) {
-                // Optionally filter out words from lower HSK levels
-                if let Some(hsk_filter) = args.hsk_filter {
-                    let hsk_level = hsk_list.get_hsk(&result.simplified);
-                    if hsk_level != 0 && hsk_level <= hsk_filter {
-                        continue;
-                    }
-                }
-
-                // Don't create multiple cards with the same 汉字.
-                if words_for_cards.contains_key(&result.simplified) {
-                    continue;
-                }
-
-                words_for_cards.insert(result.simplified.clone(), result);
+            CacheAction::Clean => {
+                let stats = cache.clean()?;
+                println!(
+                    "Removed {} audio files ({} bytes) from {}",
+                    stats.files,
+                    stats.bytes,
+                    cache.dir().display()
+                );
             }
         }
+        return Ok(());
+    }
 
-        let words_for_cards: Vec<_> = words_for_cards.into_values().collect();
+    let to_chunk = match (&args.file, args.text) {
+        (Some(f), None) => read_to_string(f).with_context(|| format!("Reading {}", f.display()))?,
+        (None, Some(t)) => t,
+        _ => bail!("Supply either file or sentence"),
+    };
 
-        let mut anki = Anki::new(
-            o.split_once('.').unwrap().0,
-            &args.tone_colours.unwrap_or_default(),
+    let deck_name = args
+        .deck_name
+        .clone()
+        .unwrap_or_else(|| default_deck_name(args.output.as_deref(), args.file.as_deref()));
+    let builder = DeckBuilder::new(DeckOptions {
+        hsk_filter: args.hsk_filter,
+        known_words: args
+            .known_words
+            .map(|f| KnownWords::from_file(&f))
+            .transpose()?,
+        existing: args
+            .existing
+            .as_ref()
+            .map(|f| ExistingNotes::from_file(f))
+            .transpose()?,
+        frequency_list: args
+            .frequency_list
+            .map(|f| FrequencyList::from_file(&f))
+            .transpose()?,
+        skip_top: args.skip_top,
+        skip_below: args.skip_below,
+        min_count: args.min_count,
+        order: args.order,
+        split_by: args.split_by,
+        examples: !args.no_example,
+        // What to tag notes with as where their words are from: the file, or
+        // for --text, the deck
+        source: Some(
+            args.file
+                .as_ref()
+                .and_then(|f| f.file_stem())
+                .map_or(deck_name.clone(), |f| f.to_string_lossy().into_owned()),
+        ),
+        tags: args.tag,
+    });
+    let tone_colours = args.tone_colours.unwrap_or_default();
+    let style = ReadingStyle {
+        phonetic: args.phonetic,
+        sandhi: args.sandhi,
+        colour_spoken_tone: args.colour_spoken_tone,
+        spacing: args.pinyin_spacing,
+    };
+
+    if let Some(Command::Report { json }) = args.command {
+        let segmented = builder.segment(&to_chunk);
+        let is_known = |word: &str| builder.is_known(word);
+        let report = Report::new(
+            &segmented.words,
+            |word| builder.hsk_level(word),
+            builder
+                .knows_words()
+                .then_some(&is_known as &dyn Fn(&str) -> bool),
+        );
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print!("{report}");
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Annotate { unknown_only }) = args.command {
+        let segmented = builder.segment(&to_chunk);
+        print!(
+            "{}",
+            annotate(
+                &segmented.all_tokens,
+                builder.dict(),
+                &tone_colours,
+                style,
+                |word| !unknown_only || !builder.is_known(word)
+            )
+        );
+        return Ok(());
+    }
+
+    if args.output.is_none() && args.anki_connect.is_none() && args.preview.is_none() {
+        return Ok(());
+    }
+    let tts = args.tts.then(|| {
+        let mut engine = args.tts_backend.unwrap_or_default();
+        if let Some(voice) = args.tts_voice {
+            engine = engine.with_voice(voice);
+        }
+        if let Some(speed) = args.tts_speed {
+            engine = engine.with_speed(speed);
+        }
+        TtsSettings {
+            engine,
+            options: TtsOptions {
+                concurrency: args.tts_concurrency,
+                retries: args.tts_retries,
+                rate_limit: args.tts_rate_limit,
+            },
+            cache_dir: args.cache_dir,
+        }
+    });
+    let output = OutputOptions {
+        deck_name,
+        file_name: args.file.as_ref().map(|f| {
+            f.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        }),
+        note_type: NoteType::new(
+            &tone_colours,
             &args.side,
             args.tts,
-        );
+            style,
+            &args.romanisation,
+            args.sort_key,
+        ),
+        output: args.output.zip(format),
+        merge_existing: args.merge_existing,
+        preview: args.preview,
+        anki_connect: args.anki_connect,
+        tts,
+    };
+    let report = builder.run(&to_chunk, &output).await?;
 
-        let mut filenames = None;
-        if args.tts {
-            let client = reqwest::Client::new();
-            let tts_futures = words_for_cards.iter().map(|word| {
-                tts::save_to_file(
-                    &client,
-                    &word.simplified,
-                    format!("mp3s/{}.mp3", word.simplified),
-                )
-            });
-            filenames = Some(
-                future::join_all(tts_futures)
-                    .await
-                    .into_iter()
-                    .collect::<Result<Vec<_>, _>>()
-                    .unwrap(),
+    if !report.audio_failures.is_empty() {
+        eprintln!(
+            "Couldn't get audio for {} words or examples, so their cards have none:",
+            report.audio_failures.len()
+        );
+        for failure in &report.audio_failures {
+            eprintln!("  {failure}");
+        }
+    }
+    if output.output.is_some() {
+        println!("Successfully created a deck with {} notes", report.notes);
+    }
+    if let Some(dir) = &output.preview {
+        println!(
+            "Wrote a preview of {} notes to {}",
+            report.notes,
+            Path::new(dir).join("index.html").display()
+        );
+    }
+    if let Some(pushed) = report.pushed {
+        println!(
+            "Added {} notes to the {} deck in Anki",
+            pushed.added.len(),
+            output.deck_name
+        );
+        if !pushed.already_present.is_empty() {
+            println!(
+                "Skipped {} notes that Anki already has: {}",
+                pushed.already_present.len(),
+                pushed.already_present.join(", ")
             );
         }
+    }
+    Ok(())
+}
+
+/// A --tts-rate-limit, which must be more than zero.
+fn parse_rate_limit(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 => Ok(rate),
+        Ok(_) => Err("must be more than zero".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// A --tts-concurrency, which must be at least 1.
+fn parse_concurrency(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".into()),
+        Ok(concurrency) => Ok(concurrency),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-        if let Some(ref fs) = filenames {
-            for (word, filename) in words_for_cards.iter().zip(fs.iter()) {
-                let example = if args.no_example {
-                    None
-                } else {
-                    Some(get_example(&sentences, &word.simplified))
-                };
-                anki.add_note(word, Some(filename.strip_prefix("mp3s/").unwrap()), example);
+/// The name of the deck if there's no --deck-name: the name of the output
+/// file, or of the input file if there's no output file (as for AnkiConnect).
+fn default_deck_name(output: Option<&str>, file: Option<&Path>) -> String {
+    output
+        .map(Path::new)
+        .or(file)
+        .and_then(|f| f.file_stem())
+        .map_or("zw_to_anki".into(), |f| f.to_string_lossy().into_owned())
+}
+
+/// Print every reading of `word` (or of its parts, if it isn't in the
+/// dictionary) in every romanisation, with its definitions, and pinyin written
+/// with `spacing`.
+fn lookup(dict: &CEDict, word: &str, spacing: Spacing) {
+    for result in dict.get(word) {
+        println!("{}", result.simplified);
+        for (pinyin, definitions) in &result.pinyins {
+            for romanisation in [
+                Romanisation::Pinyin,
+                Romanisation::Zhuyin,
+                Romanisation::WadeGiles,
+                Romanisation::Yale,
+                Romanisation::Ipa,
+            ] {
+                println!(
+                    "  {:<11} {}",
+                    format!("{}:", romanisation.name()),
+                    pinyin.romanise(romanisation, spacing)
+                );
             }
-        } else {
-            for word in &words_for_cards {
-                let example = if args.no_example {
-                    None
-                } else {
-                    Some(get_example(&sentences, &word.simplified))
-                };
-                anki.add_note(word, None, example);
+            for definition in definitions {
+                println!("    - {definition}");
             }
         }
+    }
+}
 
-        anki.write_to_file(
-            &o,
-            filenames.unwrap_or_default().iter().map(|s| &**s).collect(),
-        );
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        println!(
-            "Successfully created a deck with {} notes",
-            words_for_cards.len()
+    #[test]
+    fn test_default_deck_name() {
+        assert_eq!(default_deck_name(Some("../decks/ST04.apkg"), None), "ST04");
+        assert_eq!(default_deck_name(Some("my.book.apkg"), None), "my.book");
+        assert_eq!(default_deck_name(Some("decks/ST04"), None), "ST04");
+        // Without an output file, as for AnkiConnect, the input file names the deck
+        assert_eq!(
+            default_deck_name(None, Some(Path::new("books/ST04.txt"))),
+            "ST04"
+        );
+        assert_eq!(
+            default_deck_name(Some("ST05.tsv"), Some(Path::new("ST04.txt"))),
+            "ST05"
         );
+        assert_eq!(default_deck_name(None, None), "zw_to_anki");
     }
-}
 
-fn get_example<'a>(sentences: &[&'a str], word: &str) -> &'a str {
-    sentences
-        .iter()
-        .find(|e| e.contains(word))
-        .expect("There should be at least one sentence that contains each word")
+    #[test]
+    fn test_tts_limits() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["zw_to_anki", "-t", "你好"], args].concat());
+        assert_eq!(
+            parse(&["--tts-concurrency", "2"]).unwrap().tts_concurrency,
+            2
+        );
+        assert!(parse(&["--tts-concurrency", "0"]).is_err());
+        assert_eq!(
+            parse(&["--tts-rate-limit", "0.5"]).unwrap().tts_rate_limit,
+            Some(0.5)
+        );
+        assert!(parse(&["--tts-rate-limit", "0"]).is_err());
+        assert!(parse(&["--tts-rate-limit", "-1"]).is_err());
+    }
 }
@@ -1,4 +1,109 @@
-use crate::dict::Tone;
+use crate::dict::{PinYinSyllable, Tone};
+use clap::ValueEnum;
+
+/// How readings are written out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Phonetic {
+    /// Tone-marked pinyin, e.g. bāngzhù
+    #[default]
+    Pinyin,
+    /// Zhuyin (bopomofo), e.g. ㄅㄤ ㄓㄨˋ
+    Zhuyin,
+    /// Pinyin followed by zhuyin
+    Both,
+}
+
+/// Whether to show tones as written in the dictionary or as they're spoken,
+/// e.g. 你好 is written nǐhǎo but said níhǎo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Sandhi {
+    /// Dictionary tones only
+    #[default]
+    Off,
+    /// Spoken tones only
+    Replace,
+    /// Dictionary tones, with the spoken tone after any syllable where it differs
+    Alongside,
+}
+
+/// How the syllables of a word are separated in pinyin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Spacing {
+    /// Run together, with an apostrophe where needed, e.g. Xī'ān
+    #[default]
+    Joined,
+    /// Separated by spaces, e.g. Xī ān
+    Spaced,
+}
+
+/// Everything about how readings are written out on cards
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadingStyle {
+    pub phonetic: Phonetic,
+    pub sandhi: Sandhi,
+    /// Colour syllables by their spoken tone rather than their dictionary tone
+    pub colour_spoken_tone: bool,
+    pub spacing: Spacing,
+}
+
+/// Chinese digits, which 一 is read as yī next to.
+const DIGITS: &str = "〇零一二两三四五六七八九十";
+/// Chinese digits and the larger numerals.
+const NUMERALS: &str = "〇零一二两三四五六七八九十百千万亿";
+
+/// The tones that `syllables`, the reading of `hanzi`, are spoken with: a
+/// third tone before another third tone becomes a second tone, 不 becomes a
+/// second tone before a fourth tone, and 一 becomes a second tone before a
+/// fourth tone and a fourth tone before any other, unless it's counting.
+pub fn spoken_tones(hanzi: &str, syllables: &[PinYinSyllable]) -> Vec<Option<Tone>> {
+    // We only know which syllable belongs to which character if there's one of each
+    let chars: Vec<_> = hanzi.chars().collect();
+    let char_at = |i: usize| (chars.len() == syllables.len()).then(|| chars[i]);
+
+    // 一 keeps its tone as an ordinal or in a number, as in 第一天 and 十一月,
+    // though not before 百, 千 and so on, as in 一百 (yìbǎi)
+    let is_number = |i: usize| {
+        let before = i.checked_sub(1).and_then(char_at);
+        before.is_some_and(|c| c == '第' || NUMERALS.contains(c))
+            || char_at(i + 1).is_some_and(|c| DIGITS.contains(c))
+    };
+
+    let mut tones: Vec<_> = syllables.iter().map(|pys| pys.tone).collect();
+    for (i, pair) in syllables.windows(2).enumerate() {
+        let next = pair[1].tone;
+        tones[i] = match (char_at(i), pair[0].tone, next) {
+            (_, Some(Tone::Third), Some(Tone::Third)) => Some(Tone::Second),
+            (Some('不'), Some(Tone::Fourth), Some(Tone::Fourth)) => Some(Tone::Second),
+            (Some('一'), tone, _) if is_number(i) => tone,
+            (Some('一'), Some(Tone::First), Some(Tone::Fourth)) => Some(Tone::Second),
+            (Some('一'), Some(Tone::First), Some(Tone::First | Tone::Second | Tone::Third)) => {
+                Some(Tone::Fourth)
+            }
+            (_, tone, _) => tone,
+        };
+    }
+    tones
+}
+
+/// Join the written-out form of each syllable (e.g. with tone marks or colours)
+/// into one word. When joined, an apostrophe goes before any syllable after the
+/// first that starts with a, o or e, so that 西安 (Xī'ān) isn't read as 先 (xiān).
+pub fn join_pinyin(syllables: &[(PinYinSyllable, String)], spacing: Spacing) -> String {
+    let mut joined = String::new();
+    for (i, (pys, written)) in syllables.iter().enumerate() {
+        if i > 0 {
+            match spacing {
+                Spacing::Spaced => joined.push(' '),
+                Spacing::Joined if pys.text.starts_with(['a', 'o', 'e', 'A', 'O', 'E']) => {
+                    joined.push('\'')
+                }
+                Spacing::Joined => {}
+            }
+        }
+        joined += written;
+    }
+    joined
+}
 
 const VOWELS: &str = "aeiouüAEIOUÜ";
 
@@ -9,61 +114,69 @@ pub fn add_diacritic(text: &str, tone: Option<Tone>) -> String {
     };
 
     // We have a tone, so it's regular pinyin (rather than being some english for e.g.)
-    // If c is a consonant and v is a vowel, pinyin is always c*v+c*
-    let first_vowel_idx = text
-        .chars()
-        .position(|c| VOWELS.contains(c))
-        .expect("Pinyin always contains a vowel");
-    let last_vowel_idx = text.len()
-        - 1
-        - text
-            .chars()
-            .rev()
-            .position(|c| VOWELS.contains(c))
-            .expect("Pinyin always contains a vowel");
+    // If c is a consonant and v is a vowel, pinyin is usually c*v+c*
+    let is_vowel = |c| VOWELS.contains(c);
+    let (Some(first_vowel_idx), Some(last_vowel_idx)) = (text.find(is_vowel), text.rfind(is_vowel))
+    else {
+        // Otherwise it's ê or a syllabic nasal like m, ng or hm, which take a
+        // combining mark, or something like erhua's r that can't take one.
+        return match text.find(|c| "êÊmMnN".contains(c)) {
+            Some(i) => {
+                let (before, after) =
+                    text.split_at(i + text[i..].chars().next().unwrap().len_utf8());
+                format!(
+                    "{before}{}{after}",
+                    COMBINING_MARKS[usize::from(tone_which_needs_adding) - 1]
+                )
+            }
+            None => text.into(),
+        };
+    };
+    let after_last_vowel =
+        last_vowel_idx + text[last_vowel_idx..].chars().next().unwrap().len_utf8();
 
     format!(
         "{}{}{}",
         &text[..first_vowel_idx],
         add_diacritic_to_vowel_group(
-            &text[first_vowel_idx..=last_vowel_idx],
+            &text[first_vowel_idx..after_last_vowel],
             tone_which_needs_adding
         ),
-        &text[last_vowel_idx + 1..]
+        &text[after_last_vowel..]
     )
 }
 
 fn add_diacritic_to_vowel_group(vowels: &str, tone: Tone) -> String {
     // From https://en.wikipedia.org/wiki/Pinyin#Rules_for_placing_the_tone_mark
-    if vowels.len() == 1 {
-        return add_diacritic_to_char(vowels.chars().next().unwrap(), tone).to_string();
-    } else if vowels.contains(|c| c == 'a' || c == 'e') {
+    if vowels.chars().count() == 1 {
+        add_diacritic_to_char(vowels.chars().next().unwrap(), tone).to_string()
+    } else if vowels.contains(['a', 'e', 'A', 'E']) {
         // If there is an a or an e, it will take the tone mark
-        return vowels
+        vowels
             .chars()
             .map(|c| {
-                if c == 'a' || c == 'e' {
+                if "aeAE".contains(c) {
                     add_diacritic_to_char(c, tone)
                 } else {
                     c
                 }
             })
-            .collect();
-    } else if vowels.contains("ou") {
+            .collect()
+    } else if vowels.to_lowercase().contains("ou") {
         // If there is an ou, then the o takes the tone mark
-        return vowels
+        vowels
             .chars()
             .map(|c| {
-                if c == 'o' {
+                if c == 'o' || c == 'O' {
                     add_diacritic_to_char(c, tone)
                 } else {
                     c
                 }
             })
-            .collect();
+            .collect()
     } else {
         // Otherwise, the second vowel takes the tone mark
-        return vowels
+        vowels
             .chars()
             .enumerate()
             .map(|(i, c)| {
@@ -73,10 +186,13 @@ fn add_diacritic_to_vowel_group(vowels: &str, tone: Tone) -> String {
                     c
                 }
             })
-            .collect();
+            .collect()
     }
 }
 
+/// Tone marks for letters that don't have precomposed forms for every tone
+const COMBINING_MARKS: [char; 4] = ['\u{304}', '\u{301}', '\u{30C}', '\u{300}'];
+
 const AS: [char; 5] = ['ā', 'á', 'ǎ', 'à', 'a'];
 const ES: [char; 5] = ['ē', 'é', 'ě', 'è', 'e'];
 const IS: [char; 5] = ['ī', 'í', 'ǐ', 'ì', 'i'];
@@ -102,6 +218,501 @@ fn add_diacritic_to_char(c: char, tone: Tone) -> char {
     }
 }
 
+/// Initials as written in pinyin, zhuyin, Wade-Giles, Yale and IPA
+type Initial = (
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+);
+
+const INITIALS: [Initial; 21] = [
+    ("zh", "ㄓ", "ch", "j", "ʈʂ"),
+    ("ch", "ㄔ", "ch'", "ch", "ʈʂʰ"),
+    ("sh", "ㄕ", "sh", "sh", "ʂ"),
+    ("b", "ㄅ", "p", "b", "p"),
+    ("p", "ㄆ", "p'", "p", "pʰ"),
+    ("m", "ㄇ", "m", "m", "m"),
+    ("f", "ㄈ", "f", "f", "f"),
+    ("d", "ㄉ", "t", "d", "t"),
+    ("t", "ㄊ", "t'", "t", "tʰ"),
+    ("n", "ㄋ", "n", "n", "n"),
+    ("l", "ㄌ", "l", "l", "l"),
+    ("g", "ㄍ", "k", "g", "k"),
+    ("k", "ㄎ", "k'", "k", "kʰ"),
+    ("h", "ㄏ", "h", "h", "x"),
+    ("j", "ㄐ", "ch", "j", "tɕ"),
+    ("q", "ㄑ", "ch'", "ch", "tɕʰ"),
+    ("x", "ㄒ", "hs", "sy", "ɕ"),
+    ("r", "ㄖ", "j", "r", "ɻ"),
+    ("z", "ㄗ", "ts", "dz", "ts"),
+    ("c", "ㄘ", "ts'", "ts", "tsʰ"),
+    ("s", "ㄙ", "s", "s", "s"),
+];
+
+/// Finals as written in pinyin, zhuyin, Wade-Giles after an initial,
+/// Wade-Giles on their own, Yale and IPA. The pinyin is in full form, i.e. as
+/// it'd be written after an initial, with 'iu', 'ui' and 'un' expanded.
+type Final = (
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+);
+
+const FINALS: [Final; 38] = [
+    ("a", "ㄚ", "a", "a", "a", "a"),
+    ("o", "ㄛ", "o", "o", "wo", "o"),
+    ("e", "ㄜ", "ê", "o", "e", "ɤ"),
+    ("ê", "ㄝ", "eh", "eh", "e", "ɛ"),
+    ("ai", "ㄞ", "ai", "ai", "ai", "aɪ"),
+    ("ei", "ㄟ", "ei", "ei", "ei", "eɪ"),
+    ("ao", "ㄠ", "ao", "ao", "au", "ɑʊ"),
+    ("ou", "ㄡ", "ou", "ou", "ou", "oʊ"),
+    ("an", "ㄢ", "an", "an", "an", "an"),
+    ("en", "ㄣ", "ên", "ên", "en", "ən"),
+    ("ang", "ㄤ", "ang", "ang", "ang", "ɑŋ"),
+    ("eng", "ㄥ", "êng", "êng", "eng", "ɤŋ"),
+    ("ong", "ㄨㄥ", "ung", "ung", "ung", "ʊŋ"),
+    ("er", "ㄦ", "êrh", "êrh", "er", "aɚ"),
+    ("i", "ㄧ", "i", "i", "i", "i"),
+    ("ia", "ㄧㄚ", "ia", "ya", "ya", "ja"),
+    ("ie", "ㄧㄝ", "ieh", "yeh", "ye", "jɛ"),
+    ("io", "ㄧㄛ", "io", "yo", "yo", "jɔ"),
+    ("iao", "ㄧㄠ", "iao", "yao", "yau", "jɑʊ"),
+    ("iou", "ㄧㄡ", "iu", "yu", "you", "joʊ"),
+    ("ian", "ㄧㄢ", "ien", "yen", "yan", "jɛn"),
+    ("in", "ㄧㄣ", "in", "yin", "in", "in"),
+    ("iang", "ㄧㄤ", "iang", "yang", "yang", "jɑŋ"),
+    ("ing", "ㄧㄥ", "ing", "ying", "ing", "iŋ"),
+    ("iong", "ㄩㄥ", "iung", "yung", "yung", "jʊŋ"),
+    ("u", "ㄨ", "u", "wu", "u", "u"),
+    ("ua", "ㄨㄚ", "ua", "wa", "wa", "wa"),
+    ("uo", "ㄨㄛ", "uo", "wo", "wo", "wo"),
+    ("uai", "ㄨㄞ", "uai", "wai", "wai", "waɪ"),
+    ("uei", "ㄨㄟ", "ui", "wei", "wei", "weɪ"),
+    ("uan", "ㄨㄢ", "uan", "wan", "wan", "wan"),
+    ("uen", "ㄨㄣ", "un", "wên", "wun", "wən"),
+    ("uang", "ㄨㄤ", "uang", "wang", "wang", "wɑŋ"),
+    ("ueng", "ㄨㄥ", "ueng", "wêng", "weng", "wɤŋ"),
+    ("ü", "ㄩ", "ü", "yü", "yu", "y"),
+    ("üe", "ㄩㄝ", "üeh", "yüeh", "ywe", "ɥɛ"),
+    ("üan", "ㄩㄢ", "üan", "yüan", "ywan", "ɥɛn"),
+    ("ün", "ㄩㄣ", "ün", "yün", "yun", "yn"),
+];
+
+/// Syllables without a vowel, as written in pinyin, zhuyin and IPA: syllabic
+/// nasals in interjections like 嗯 (ng), and the r of erhua when written as its
+/// own syllable.
+const SYLLABIC_CONSONANTS: [(&str, &str, &str); 6] = [
+    ("m", "ㄇ", "m̩"),
+    ("n", "ㄋ", "n̩"),
+    ("ng", "ㄫ", "ŋ̩"),
+    ("hm", "ㄏㄇ", "hm̩"),
+    ("hng", "ㄏㄫ", "hŋ̩"),
+    ("r", "ㄦ", "ɚ"),
+];
+
+/// Ways of writing readings other than the tone-marked pinyin on cards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Romanisation {
+    /// Tone-marked pinyin, e.g. bāngzhù
+    Pinyin,
+    /// Zhuyin (bopomofo), e.g. ㄅㄤ ㄓㄨˋ
+    Zhuyin,
+    /// Wade-Giles, e.g. pang¹-chu⁴
+    WadeGiles,
+    /// Yale, e.g. bāngjù
+    Yale,
+    /// The International Phonetic Alphabet, e.g. pɑŋ˥.ʈʂu˥˩
+    Ipa,
+}
+
+impl Romanisation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pinyin => "Pinyin",
+            Self::Zhuyin => "Zhuyin",
+            Self::WadeGiles => "Wade-Giles",
+            Self::Yale => "Yale",
+            Self::Ipa => "IPA",
+        }
+    }
+
+    /// What goes between the syllables of a word
+    pub fn separator(&self) -> &'static str {
+        match self {
+            Self::Pinyin | Self::Yale => "",
+            Self::Zhuyin => " ",
+            Self::WadeGiles => "-",
+            Self::Ipa => ".",
+        }
+    }
+}
+
+/// Convert a pinyin syllable (without tone) to zhuyin, with tone marks.
+/// Anything that isn't a pinyin syllable, like '·', is returned as is.
+pub fn to_zhuyin(text: &str, tone: Option<Tone>) -> String {
+    let syllable = text.to_lowercase().replace('v', "ü");
+    let (syllable, erhua) = split_erhua(&syllable);
+    let Some(zhuyin) = syllable_to_zhuyin(syllable) else {
+        return text.into();
+    };
+    let zhuyin = match tone {
+        None | Some(Tone::First) => zhuyin,
+        Some(Tone::Second) => zhuyin + "ˊ",
+        Some(Tone::Third) => zhuyin + "ˇ",
+        Some(Tone::Fourth) => zhuyin + "ˋ",
+        Some(Tone::Fifth) => format!("˙{zhuyin}"),
+    };
+    // Erhua is written as ㄦ after the syllable's tone mark
+    if erhua {
+        zhuyin + "ㄦ"
+    } else {
+        zhuyin
+    }
+}
+
+/// Convert a pinyin syllable (without tone) to `romanisation`, with tones.
+/// Anything that isn't a pinyin syllable, like '·', is returned as is.
+pub fn to_romanisation(text: &str, tone: Option<Tone>, romanisation: Romanisation) -> String {
+    let system = match romanisation {
+        Romanisation::Pinyin => return add_diacritic(text, tone),
+        Romanisation::Zhuyin => return to_zhuyin(text, tone),
+        other => other,
+    };
+
+    let syllable = text.to_lowercase().replace('v', "ü");
+    let (syllable, erhua) = split_erhua(&syllable);
+    let spelling = match SYLLABIC_CONSONANTS.iter().find(|(py, ..)| *py == syllable) {
+        Some((_, _, ipa)) if system == Romanisation::Ipa => ipa.to_string(),
+        Some((py, ..)) => py.to_string(),
+        None => match split_syllable(syllable) {
+            Some((initial, final_)) => spell(initial, final_, system),
+            None => return text.into(),
+        },
+    };
+    let spelling = match (erhua, system) {
+        (false, _) => spelling,
+        (true, Romanisation::Ipa) => spelling + "˞",
+        (true, _) => spelling + "r",
+    };
+
+    let spelling = match (system, tone) {
+        (Romanisation::Yale, _) => add_diacritic(&spelling, tone),
+        (_, None | Some(Tone::Fifth)) => spelling,
+        (Romanisation::WadeGiles, Some(tone)) => {
+            spelling + ["¹", "²", "³", "⁴"][usize::from(tone) - 1]
+        }
+        (_, Some(tone)) => spelling + ["˥", "˧˥", "˨˩˦", "˥˩"][usize::from(tone) - 1],
+    };
+
+    // Keep the capital of e.g. a place name, except in IPA where it'd mean something else
+    match text.chars().next() {
+        Some(c) if c.is_uppercase() && system != Romanisation::Ipa => {
+            let mut chars = spelling.chars();
+            chars.next().map_or(spelling.clone(), |first| {
+                first.to_uppercase().chain(chars).collect()
+            })
+        }
+        _ => spelling,
+    }
+}
+
+/// Spell out a syllable in Wade-Giles, Yale or IPA, without its tone. A
+/// missing final is the 'i' of zhi, ci and so on.
+fn spell(initial: Option<Initial>, final_: Option<Final>, system: Romanisation) -> String {
+    let (py_initial, _, wg_initial, yale_initial, ipa_initial) =
+        initial.unwrap_or(("", "", "", "", ""));
+    let retroflex = matches!(py_initial, "zh" | "ch" | "sh" | "r");
+
+    let Some((py_final, _, wg_final, wg_alone, yale_final, ipa_final)) = final_ else {
+        return match system {
+            Romanisation::WadeGiles => match py_initial {
+                "z" => "tzu".into(),
+                "c" => "tz'u".into(),
+                "s" => "ssu".into(),
+                _ => format!("{wg_initial}ih"),
+            },
+            Romanisation::Yale => match py_initial {
+                "z" | "r" => yale_initial.into(),
+                _ if retroflex => format!("{yale_initial}r"),
+                _ => format!("{yale_initial}z"),
+            },
+            _ => match py_initial {
+                "r" => "ɻ̩".into(),
+                _ if retroflex => format!("{ipa_initial}ɻ̩"),
+                _ => format!("{ipa_initial}ɹ̩"),
+            },
+        };
+    };
+
+    match system {
+        Romanisation::WadeGiles if initial.is_none() => wg_alone.into(),
+        Romanisation::WadeGiles => {
+            let wg_final = match (py_initial, py_final) {
+                ("g" | "k" | "h", "e") => "o",
+                ("g" | "k", "uei") => "uei",
+                ("g" | "k" | "h" | "sh", "uo") => "uo",
+                (_, "uo") => "o",
+                _ => wg_final,
+            };
+            format!("{wg_initial}{wg_final}")
+        }
+        Romanisation::Yale if initial.is_none() => match py_final {
+            "i" | "in" | "ing" => format!("y{yale_final}"),
+            "u" => "wu".into(),
+            "uen" => "wen".into(),
+            _ => yale_final.into(),
+        },
+        // The y of 'sy' doubles as the i of any final after it, e.g. 'sya'
+        Romanisation::Yale if py_initial == "x" => format!(
+            "{yale_initial}{}",
+            yale_final.strip_prefix('y').unwrap_or(yale_final)
+        ),
+        Romanisation::Yale => format!("{yale_initial}{yale_final}"),
+        _ => format!("{ipa_initial}{ipa_final}"),
+    }
+}
+
+/// Split off the r of an erhua syllable like 'nar', if it has one.
+fn split_erhua(syllable: &str) -> (&str, bool) {
+    match syllable.strip_suffix('r') {
+        Some(stem) if syllable != "er" && split_syllable(stem).is_some() => (stem, true),
+        _ => (syllable, false),
+    }
+}
+
+fn syllable_to_zhuyin(syllable: &str) -> Option<String> {
+    if let Some((_, zy, _)) = SYLLABIC_CONSONANTS.iter().find(|(py, ..)| *py == syllable) {
+        return Some(zy.to_string());
+    }
+    let (initial, final_) = split_syllable(syllable)?;
+    Some(format!(
+        "{}{}",
+        initial.map_or("", |i| i.1),
+        // The 'i' in zhi, chi, shi, ri, zi, ci, si isn't written in zhuyin
+        final_.map_or("", |f| f.1)
+    ))
+}
+
+/// Split a lowercase pinyin syllable into its initial and final, with the
+/// final in full form. There's no final for the 'i' of zhi, ci and so on,
+/// which is a different sound to the 'i' of other syllables.
+fn split_syllable(syllable: &str) -> Option<(Option<Initial>, Option<Final>)> {
+    let initial = INITIALS
+        .iter()
+        .find(|(py, ..)| syllable.starts_with(py))
+        .copied();
+    let py_initial = initial.map_or("", |i| i.0);
+    let rest = &syllable[py_initial.len()..];
+
+    let final_ = match (py_initial, rest) {
+        ("zh" | "ch" | "sh" | "r" | "z" | "c" | "s", "i") => return Some((initial, None)),
+        // Undo the spelling rules for syllables without an initial
+        ("", _) if rest.starts_with('y') => match &rest[1..] {
+            "i" | "in" | "ing" => rest[1..].to_string(),
+            r if r.starts_with('u') => format!("ü{}", &r[1..]),
+            r => format!("i{r}"),
+        },
+        ("", _) if rest.starts_with('w') => match &rest[1..] {
+            "u" => "u".to_string(),
+            r => format!("u{r}"),
+        },
+        // j, q and x are only ever followed by ü, so it's written as u
+        ("j" | "q" | "x", _) if rest.starts_with('u') => format!("ü{}", &rest[1..]),
+        (_, "iu") => "iou".to_string(),
+        (_, "ui") => "uei".to_string(),
+        (_, "un") => "uen".to_string(),
+        _ => rest.to_string(),
+    };
+
+    FINALS
+        .iter()
+        .find(|(py, ..)| *py == final_)
+        .map(|f| (initial, Some(*f)))
+}
+
+/// Every syllable that pinyin allows, as written (so 'yu' rather than 'ü'),
+/// without erhua. Those without a vowel, like 'ng', are in
+/// `SYLLABIC_CONSONANTS` instead.
+const SYLLABLES: &str = "
+    a ai an ang ao
+    ba bai ban bang bao bei ben beng bi bian biao bie bin bing bo bu
+    ca cai can cang cao ce cen ceng cha chai chan chang chao che chen cheng chi chong chou chu chua chuai chuan chuang chui chun chuo ci cong cou cu cuan cui cun cuo
+    da dai dan dang dao de dei den deng di dia dian diao die ding diu dong dou du duan dui dun duo
+    e ê ei en eng er
+    fa fan fang fei fen feng fo fou fu
+    ga gai gan gang gao ge gei gen geng gong gou gu gua guai guan guang gui gun guo
+    ha hai han hang hao he hei hen heng hong hou hu hua huai huan huang hui hun huo
+    ji jia jian jiang jiao jie jin jing jiong jiu ju juan jue jun
+    ka kai kan kang kao ke kei ken keng kong kou ku kua kuai kuan kuang kui kun kuo
+    la lai lan lang lao le lei leng li lia lian liang liao lie lin ling liu lo long lou lu luan lun luo lü lüe
+    ma mai man mang mao me mei men meng mi mian miao mie min ming miu mo mou mu
+    na nai nan nang nao ne nei nen neng ni nian niang niao nie nin ning niu nong nou nu nuan nuo nü nüe
+    o ou
+    pa pai pan pang pao pei pen peng pi pian piao pie pin ping po pou pu
+    qi qia qian qiang qiao qie qin qing qiong qiu qu quan que qun
+    ran rang rao re ren reng ri rong rou ru rua ruan rui run ruo
+    sa sai san sang sao se sen seng sha shai shan shang shao she shei shen sheng shi shou shu shua shuai shuan shuang shui shun shuo si song sou su suan sui sun suo
+    ta tai tan tang tao te tei teng ti tian tiao tie ting tong tou tu tuan tui tun tuo
+    wa wai wan wang wei wen weng wo wu
+    xi xia xian xiang xiao xie xin xing xiong xiu xu xuan xue xun
+    ya yan yang yao ye yi yin ying yo yong you yu yuan yue yun
+    za zai zan zang zao ze zei zen zeng zha zhai zhan zhang zhao zhe zhei zhen zheng zhi zhong zhou zhu zhua zhuai zhuan zhuang zhui zhun zhuo zi zong zou zu zuan zui zun zuo
+";
+
+/// The longest pinyin syllable, 'zhuang', is six letters long.
+const MAX_SYLLABLE_LEN: usize = 6;
+
+/// Parse pinyin as a user might write it: tone-marked ('bāngzhù'), numbered with
+/// or without spaces ('bang1 zhu4', 'bang1zhu4'), or without tones at all, with ü
+/// written as 'ü', 'v' or 'u:'. Syllables that are run together are split
+/// against the syllables that pinyin allows, so 'fangan' is 'fan gan' and
+/// 'xian' is 'xian', while an apostrophe or hyphen splits explicitly, as in
+/// "Xi'an".
+///
+/// If the input has any tones then syllables without one are taken to be
+/// neutral, and otherwise every syllable has no tone.
+pub fn parse_pinyin(text: &str) -> Result<Vec<PinYinSyllable>, String> {
+    let text = text.replace("u:", "ü").replace("U:", "Ü");
+    let has_tones = text
+        .chars()
+        .any(|c| c.is_ascii_digit() || strip_diacritic(c).1.is_some());
+
+    let mut syllables = vec![];
+    for chunk in text.split(|c: char| c.is_whitespace() || "'’-".contains(c)) {
+        if chunk.is_empty() {
+            continue;
+        }
+        if chunk == "·" {
+            syllables.push(PinYinSyllable::from(chunk));
+            continue;
+        }
+        for (piece, digit_tone) in split_on_tone_numbers(chunk)? {
+            let (letters, marks): (Vec<_>, Vec<_>) = piece
+                .chars()
+                .map(|c| match strip_diacritic(c) {
+                    ('v', tone) => ('ü', tone),
+                    ('V', tone) => ('Ü', tone),
+                    other => other,
+                })
+                .unzip();
+            let lengths = segment(&letters)
+                .ok_or_else(|| format!("Couldn't split '{piece}' into pinyin syllables"))?;
+
+            let mut start = 0;
+            for (i, len) in lengths.iter().enumerate() {
+                let end = start + len;
+                let mark = marks[start..end].iter().find_map(|t| *t);
+                let tone = match (mark, i == lengths.len() - 1) {
+                    (Some(tone), _) => Some(tone),
+                    (None, true) if digit_tone.is_some() => digit_tone,
+                    (None, _) if has_tones => Some(Tone::Fifth),
+                    (None, _) => None,
+                };
+                syllables.push(PinYinSyllable {
+                    text: letters[start..end].iter().collect(),
+                    tone,
+                });
+                start = end;
+            }
+        }
+    }
+
+    if syllables.is_empty() {
+        return Err("Expected some pinyin".into());
+    }
+    Ok(syllables)
+}
+
+/// Split e.g. 'bang1zhu4' into ('bang', 1) and ('zhu', 4). 0 and 5 are both
+/// taken to mean the neutral tone.
+fn split_on_tone_numbers(chunk: &str) -> Result<Vec<(&str, Option<Tone>)>, String> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    for (i, c) in chunk.char_indices() {
+        let Some(digit) = c.to_digit(10) else {
+            continue;
+        };
+        let tone = match digit {
+            0 | 5 => Tone::Fifth,
+            1..=4 => Tone::from(digit as u8),
+            _ => return Err(format!("Invalid tone number {digit} in '{chunk}'")),
+        };
+        if start == i {
+            return Err(format!("Tone number without a syllable in '{chunk}'"));
+        }
+        pieces.push((&chunk[start..i], Some(tone)));
+        start = i + c.len_utf8();
+    }
+    if start < chunk.len() {
+        pieces.push((&chunk[start..], None));
+    }
+    Ok(pieces)
+}
+
+/// The lengths of the syllables that `letters` splits into, preferring longer
+/// syllables first.
+fn segment(letters: &[char]) -> Option<Vec<usize>> {
+    let whole = letters.iter().collect::<String>().to_lowercase();
+    if SYLLABIC_CONSONANTS.iter().any(|(py, ..)| *py == whole) {
+        return Some(vec![letters.len()]);
+    }
+    segment_from(letters, 0)
+}
+
+fn segment_from(letters: &[char], start: usize) -> Option<Vec<usize>> {
+    if start == letters.len() {
+        return Some(vec![]);
+    }
+    let longest = MAX_SYLLABLE_LEN.min(letters.len() - start);
+    (1..=longest).rev().find_map(|len| {
+        let candidate = letters[start..start + len]
+            .iter()
+            .collect::<String>()
+            .to_lowercase();
+        if !is_syllable(&candidate, start == 0) {
+            return None;
+        }
+        let mut rest = segment_from(letters, start + len)?;
+        rest.insert(0, len);
+        Some(rest)
+    })
+}
+
+fn is_syllable(syllable: &str, word_initial: bool) -> bool {
+    // A syllable starting with a vowel needs an apostrophe before it unless it
+    // starts the word, so 'xian' is never 'xi an'
+    if !word_initial && syllable.starts_with(['a', 'o', 'e', 'ê']) {
+        return false;
+    }
+    // Any syllable can take the r of erhua, as in 'nar' or 'huar'
+    let stem = syllable.strip_suffix('r');
+    SYLLABLES
+        .split_whitespace()
+        .any(|s| s == syllable || Some(s) == stem)
+}
+
+/// Split a possibly tone-marked vowel into the vowel and its tone.
+fn strip_diacritic(c: char) -> (char, Option<Tone>) {
+    let lower = c.to_lowercase().next().unwrap();
+    for vowels in [AS, ES, IS, OS, US, UUS] {
+        if let Some(i) = vowels[..4].iter().position(|v| *v == lower) {
+            let base = if c.is_uppercase() {
+                vowels[4].to_uppercase().next().unwrap()
+            } else {
+                vowels[4]
+            };
+            return (base, Some(Tone::from(i as u8 + 1)));
+        }
+    }
+    (c, None)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -113,8 +724,210 @@ mod test {
             ("Xi", Tone::First, "Xī"),
             ("Er", Tone::Third, "Ěr"),
             ("shuang", Tone::Fourth, "shuàng"),
+            ("lü", Tone::Fourth, "lǜ"),
+            ("lüe", Tone::Fourth, "lüè"),
+            ("Ou", Tone::First, "Ōu"),
+            ("nar", Tone::Third, "nǎr"),
+            ("m", Tone::Second, "m\u{301}"),
+            ("ng", Tone::Second, "n\u{301}g"),
+            ("hm", Tone::Fourth, "hm\u{300}"),
+            ("ê", Tone::Second, "ê\u{301}"),
+            ("r", Tone::Fourth, "r"),
         ] {
             assert_eq!(add_diacritic(text, Some(tone)), expected);
         }
     }
+
+    #[test]
+    fn test_zhuyin() {
+        for (text, tone, expected) in [
+            ("bang", Some(Tone::First), "ㄅㄤ"),
+            ("zhu", Some(Tone::Fourth), "ㄓㄨˋ"),
+            ("shi", Some(Tone::Fourth), "ㄕˋ"),
+            ("ni", Some(Tone::Third), "ㄋㄧˇ"),
+            ("ma", Some(Tone::Fifth), "˙ㄇㄚ"),
+            ("Xi", Some(Tone::First), "ㄒㄧ"),
+            ("yong", Some(Tone::Third), "ㄩㄥˇ"),
+            ("yo", Some(Tone::First), "ㄧㄛ"),
+            ("you", Some(Tone::Third), "ㄧㄡˇ"),
+            ("yuan", Some(Tone::Second), "ㄩㄢˊ"),
+            ("yun", Some(Tone::Second), "ㄩㄣˊ"),
+            ("wo", Some(Tone::Third), "ㄨㄛˇ"),
+            ("wu", Some(Tone::Third), "ㄨˇ"),
+            ("jue", Some(Tone::Second), "ㄐㄩㄝˊ"),
+            ("xun", Some(Tone::Second), "ㄒㄩㄣˊ"),
+            ("lü", Some(Tone::Fourth), "ㄌㄩˋ"),
+            ("lüe", Some(Tone::Fourth), "ㄌㄩㄝˋ"),
+            ("liu", Some(Tone::Second), "ㄌㄧㄡˊ"),
+            ("gui", Some(Tone::Fourth), "ㄍㄨㄟˋ"),
+            ("dun", Some(Tone::First), "ㄉㄨㄣ"),
+            ("xiong", Some(Tone::Second), "ㄒㄩㄥˊ"),
+            ("er", Some(Tone::Fourth), "ㄦˋ"),
+            ("ng", Some(Tone::Second), "ㄫˊ"),
+            ("hm", Some(Tone::Fifth), "˙ㄏㄇ"),
+            ("nar", Some(Tone::Third), "ㄋㄚˇㄦ"),
+            ("r", Some(Tone::Fifth), "˙ㄦ"),
+            ("·", None, "·"),
+            ("X", None, "X"),
+        ] {
+            assert_eq!(to_zhuyin(text, tone), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_romanisations() {
+        for (text, tone, wade_giles, yale, ipa) in [
+            ("zhong", Tone::First, "chung¹", "jūng", "ʈʂʊŋ˥"),
+            ("guo", Tone::Second, "kuo²", "gwó", "kwo˧˥"),
+            ("duo", Tone::First, "to¹", "dwō", "two˥"),
+            ("ke", Tone::Fourth, "k'o⁴", "kè", "kʰɤ˥˩"),
+            ("de", Tone::Fifth, "tê", "de", "tɤ"),
+            ("xie", Tone::Fourth, "hsieh⁴", "syè", "ɕjɛ˥˩"),
+            ("qu", Tone::Fourth, "ch'ü⁴", "chyù", "tɕʰy˥˩"),
+            ("zhi", Tone::First, "chih¹", "jr", "ʈʂɻ̩˥"),
+            ("ri", Tone::Fourth, "jih⁴", "r", "ɻ̩˥˩"),
+            ("zi", Tone::Fourth, "tzu⁴", "dz", "tsɹ̩˥˩"),
+            ("ci", Tone::Fourth, "tz'u⁴", "tsz", "tsʰɹ̩˥˩"),
+            ("si", Tone::First, "ssu¹", "sz", "sɹ̩˥"),
+            ("gui", Tone::Fourth, "kuei⁴", "gwèi", "kweɪ˥˩"),
+            ("hui", Tone::Fourth, "hui⁴", "hwèi", "xweɪ˥˩"),
+            ("liu", Tone::Second, "liu²", "lyóu", "ljoʊ˧˥"),
+            ("yi", Tone::First, "i¹", "yī", "i˥"),
+            ("you", Tone::Third, "yu³", "yǒu", "joʊ˨˩˦"),
+            ("yu", Tone::Second, "yü²", "yú", "y˧˥"),
+            ("wen", Tone::Second, "wên²", "wén", "wən˧˥"),
+            ("e", Tone::Fourth, "o⁴", "è", "ɤ˥˩"),
+            ("er", Tone::Fourth, "êrh⁴", "èr", "aɚ˥˩"),
+            ("ng", Tone::Second, "ng²", "n\u{301}g", "ŋ̩˧˥"),
+        ] {
+            let tone = Some(tone);
+            assert_eq!(
+                to_romanisation(text, tone, Romanisation::WadeGiles),
+                wade_giles
+            );
+            assert_eq!(to_romanisation(text, tone, Romanisation::Yale), yale);
+            assert_eq!(to_romanisation(text, tone, Romanisation::Ipa), ipa);
+        }
+        assert_eq!(to_romanisation("·", None, Romanisation::Yale), "·");
+    }
+
+    fn syllables(text: &str) -> Vec<(String, Option<Tone>)> {
+        parse_pinyin(text)
+            .unwrap()
+            .into_iter()
+            .map(|pys| (pys.text, pys.tone))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_pinyin() {
+        let bangzhu = vec![
+            ("bang".to_string(), Some(Tone::First)),
+            ("zhu".to_string(), Some(Tone::Fourth)),
+        ];
+        for text in [
+            "bāngzhù",
+            "bang1zhu4",
+            "bang1 zhu4",
+            "bāng zhù",
+            "bang1-zhu4",
+        ] {
+            assert_eq!(syllables(text), bangzhu, "{text}");
+        }
+
+        for text in ["lv4", "lü4", "lu:4", "lǜ"] {
+            assert_eq!(syllables(text), vec![("lü".into(), Some(Tone::Fourth))]);
+        }
+
+        let xian = vec![
+            ("Xi".to_string(), Some(Tone::First)),
+            ("an".to_string(), Some(Tone::First)),
+        ];
+        assert_eq!(syllables("Xī'ān"), xian);
+        assert_eq!(syllables("Xi1an1"), xian);
+        assert_eq!(syllables("xian"), vec![("xian".into(), None)]);
+
+        assert_eq!(
+            syllables("fangan"),
+            vec![("fan".into(), None), ("gan".into(), None)]
+        );
+        assert_eq!(
+            syllables("xièxie"),
+            vec![
+                ("xie".into(), Some(Tone::Fourth)),
+                ("xie".into(), Some(Tone::Fifth))
+            ]
+        );
+        assert_eq!(
+            syllables("ma0 zhuang4"),
+            vec![
+                ("ma".into(), Some(Tone::Fifth)),
+                ("zhuang".into(), Some(Tone::Fourth))
+            ]
+        );
+        assert_eq!(
+            syllables("Zhōngguórén"),
+            vec![
+                ("Zhong".into(), Some(Tone::First)),
+                ("guo".into(), Some(Tone::Second)),
+                ("ren".into(), Some(Tone::Second))
+            ]
+        );
+        assert_eq!(syllables("ng2"), vec![("ng".into(), Some(Tone::Second))]);
+        assert_eq!(syllables("yo1"), vec![("yo".into(), Some(Tone::First))]);
+        assert_eq!(syllables("lo5"), vec![("lo".into(), Some(Tone::Fifth))]);
+
+        // Erhua
+        for (text, expected) in [
+            ("zhèr", vec![("zher", Some(Tone::Fourth))]),
+            ("nar3", vec![("nar", Some(Tone::Third))]),
+            ("wanr2", vec![("wanr", Some(Tone::Second))]),
+            ("huar1", vec![("huar", Some(Tone::First))]),
+            (
+                "yìdiǎnr",
+                vec![("yi", Some(Tone::Fourth)), ("dianr", Some(Tone::Third))],
+            ),
+            ("hanrou", vec![("han", None), ("rou", None)]),
+        ] {
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|(t, tone)| (t.into(), tone))
+                .collect();
+            assert_eq!(syllables(text), expected, "{text}");
+        }
+
+        for text in [
+            "", "hello", "bang7", "3zhu", "iang", "fi1", "gin1", "biang2", "dua4",
+        ] {
+            assert!(parse_pinyin(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_spoken_tones() {
+        use Tone::*;
+        for (hanzi, pinyin, expected) in [
+            ("你好", "ni3 hao3", vec![Second, Third]),
+            ("我很好", "wo3 hen3 hao3", vec![Second, Second, Third]),
+            ("不要", "bu4 yao4", vec![Second, Fourth]),
+            ("不好", "bu4 hao3", vec![Fourth, Third]),
+            ("一个", "yi1 ge4", vec![Second, Fourth]),
+            ("一天", "yi1 tian1", vec![Fourth, First]),
+            ("一起", "yi1 qi3", vec![Fourth, Third]),
+            ("第一", "di4 yi1", vec![Fourth, First]),
+            ("第一天", "di4 yi1 tian1", vec![Fourth, First, First]),
+            ("十一月", "shi2 yi1 yue4", vec![Second, First, Fourth]),
+            ("一二三", "yi1 er4 san1", vec![First, Fourth, First]),
+            ("一百", "yi1 bai3", vec![Fourth, Third]),
+            ("一万", "yi1 wan4", vec![Second, Fourth]),
+            ("帮助", "bang1 zhu4", vec![First, Fourth]),
+        ] {
+            let syllables = parse_pinyin(pinyin).unwrap();
+            assert_eq!(
+                spoken_tones(hanzi, &syllables),
+                expected.into_iter().map(Some).collect::<Vec<_>>(),
+                "{hanzi}"
+            );
+        }
+    }
 }
@@ -0,0 +1,636 @@
+use anyhow::Context;
+use clap::ValueEnum;
+use hsk::Hsk;
+use jieba_rs::Jieba;
+use rand::seq::SliceRandom;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::anki::{Anki, NoteType};
+use crate::anki_connect::{AnkiConnect, PushReport};
+use crate::cache::AudioCache;
+use crate::chapter::split_chapters;
+use crate::dict::{CEDict, PinYin, Word};
+use crate::existing::ExistingNotes;
+use crate::export::{Card, DelimitedExporter, Exporter, Format, JsonExporter, PlecoExporter};
+use crate::frequency::FrequencyList;
+use crate::known::KnownWords;
+use crate::preview::Preview;
+use crate::tts::{self, TtsBackend, TtsEngine, TtsOptions, TtsRequest};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Order {
+    /// The order words first appear in the text
+    FirstOccurrence,
+    /// The most common words in the text first
+    Frequency,
+    /// The lowest HSK level first, with words not in HSK last
+    Hsk,
+    /// A different order every time
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SplitBy {
+    /// Put every card in the one deck
+    None,
+    /// A sub-deck per chapter, e.g. "ST04::Chapter 3"
+    Chapter,
+    /// A sub-deck per HSK level, e.g. "ST04::HSK5", and one for words not in HSK
+    Hsk,
+}
+
+/// Which words to make cards for, and what to put on them.
+pub struct DeckOptions {
+    /// Words that are in HSK at or below this level are taken to be known
+    pub hsk_filter: Option<u8>,
+    pub known_words: Option<KnownWords>,
+    /// Words that already have cards, which are skipped
+    pub existing: Option<ExistingNotes>,
+    pub frequency_list: Option<FrequencyList>,
+    /// Skip words among this many of the most frequent in `frequency_list`
+    pub skip_top: Option<usize>,
+    /// Skip words that aren't among this many of the most frequent in `frequency_list`
+    pub skip_below: Option<usize>,
+    /// Only make cards for words that appear at least this many times
+    pub min_count: usize,
+    pub order: Order,
+    pub split_by: SplitBy,
+    /// Whether to find an example sentence from the text for each word
+    pub examples: bool,
+    /// Where the text is from, e.g. "ST04", to tag notes with
+    pub source: Option<String>,
+    /// Tags to add to every note
+    pub tags: Vec<String>,
+}
+
+impl Default for DeckOptions {
+    fn default() -> Self {
+        Self {
+            hsk_filter: None,
+            known_words: None,
+            existing: None,
+            frequency_list: None,
+            skip_top: None,
+            skip_below: None,
+            min_count: 1,
+            order: Order::FirstOccurrence,
+            split_by: SplitBy::None,
+            examples: true,
+            source: None,
+            tags: vec![],
+        }
+    }
+}
+
+/// Where to send the cards made by [`DeckBuilder::run`], and what to put on
+/// them beyond the words themselves.
+pub struct OutputOptions {
+    /// The name of the deck (or Pleco category), e.g. "ST04" or "Books::ST04"
+    pub deck_name: String,
+    /// The name of the file the text is from, for the deck's description
+    pub file_name: Option<String>,
+    pub note_type: NoteType,
+    /// The file to write the cards to, and in what format
+    pub output: Option<(String, Format)>,
+    /// Whether to add the notes from `DeckOptions::existing` to an Anki deck
+    /// output, along with their media
+    pub merge_existing: bool,
+    /// A directory to write a preview of the cards to
+    pub preview: Option<String>,
+    /// The URL of AnkiConnect, to add the notes straight to Anki
+    pub anki_connect: Option<String>,
+    /// How to get audio for the cards, if they should have any
+    pub tts: Option<TtsSettings>,
+}
+
+/// How to get audio for cards.
+pub struct TtsSettings {
+    pub engine: TtsEngine,
+    pub options: TtsOptions,
+    /// The directory to cache audio in, if not the default
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// What [`DeckBuilder::run`] did.
+pub struct RunReport {
+    /// How many notes were made
+    pub notes: usize,
+    /// The words and examples whose audio couldn't be fetched, and why
+    pub audio_failures: Vec<String>,
+    /// Which notes were added to Anki through AnkiConnect, if they were sent there
+    pub pushed: Option<PushReport>,
+}
+
+/// A text split up into words.
+pub struct Segmented<'t> {
+    /// Every token, including punctuation and anything else that isn't Chinese
+    pub all_tokens: Vec<&'t str>,
+    /// Just the Chinese words, in order
+    pub words: Vec<&'t str>,
+    /// The chapter (from 1) that each of `words` is in
+    pub chapters: Vec<usize>,
+}
+
+/// A word to make a card for, with everything to go on the card apart from
+/// its audio.
+pub struct CardCandidate<'d> {
+    pub word: &'d Word,
+    /// How many times the word appears in the text
+    pub count: usize,
+    /// The chapter the word first appears in, from 1
+    pub chapter: usize,
+    /// The word's HSK level, or 0 if it isn't in HSK
+    pub hsk_level: u8,
+    pub example: Option<String>,
+    pub tags: Vec<String>,
+    /// The sub-deck to put the card in, e.g. "Chapter 3"
+    pub subdeck: Option<String>,
+}
+
+impl CardCandidate<'_> {
+    /// The card for this word at `position` in the deck, with any of its
+    /// audio in `audio`.
+    pub fn card<'a>(&'a self, position: usize, audio: &'a Audio) -> Card<'a> {
+        let sounds = audio
+            .word_utterances(self.word)
+            .into_iter()
+            .filter_map(|(text, reading)| Some((reading, audio.media_name(text, reading)?)))
+            .collect();
+        Card {
+            position,
+            word: self.word,
+            count: self.count,
+            example: self.example.as_deref(),
+            sounds,
+            example_sound: self
+                .example
+                .as_deref()
+                .and_then(|example| audio.media_name(example, None)),
+            tags: self.tags.clone(),
+            subdeck: self.subdeck.clone(),
+        }
+    }
+}
+
+/// Turns Chinese text into the words to make cards for.
+pub struct DeckBuilder {
+    jieba: Jieba,
+    dict: CEDict,
+    hsk: Hsk,
+    options: DeckOptions,
+}
+
+impl DeckBuilder {
+    pub fn new(options: DeckOptions) -> Self {
+        let mut jieba = Jieba::new();
+        let dict = CEDict::new();
+        for word in dict.dict.keys() {
+            // Add in words from the MDBG set - we don't have frequency data for these but it still
+            // seems likely that jieba having a larger vocabulary will help it to correctly segmentise.
+            jieba.add_word(word, None, None);
+        }
+        Self {
+            jieba,
+            dict,
+            hsk: Hsk::new(),
+            options,
+        }
+    }
+
+    pub fn dict(&self) -> &CEDict {
+        &self.dict
+    }
+
+    pub fn options(&self) -> &DeckOptions {
+        &self.options
+    }
+
+    /// The HSK level of `word`, or 0 if it isn't in HSK.
+    pub fn hsk_level(&self, word: &str) -> u8 {
+        self.hsk.get_hsk(word)
+    }
+
+    /// Whether there's any way of telling which words are known.
+    pub fn knows_words(&self) -> bool {
+        self.options.known_words.is_some() || self.options.hsk_filter.is_some()
+    }
+
+    /// Whether `word` is known: words at or below the HSK filter are taken to
+    /// be known, as well as any known words (in any reading).
+    pub fn is_known(&self, word: &str) -> bool {
+        self.options
+            .known_words
+            .as_ref()
+            .is_some_and(|k| k.contains(word))
+            || self.is_below_hsk_filter(word)
+    }
+
+    /// Whether every reading of `word` is known, so it doesn't need a card.
+    fn knows(&self, word: &Word) -> bool {
+        self.options
+            .known_words
+            .as_ref()
+            .is_some_and(|k| k.knows(word))
+            || self.is_below_hsk_filter(&word.simplified)
+    }
+
+    fn is_below_hsk_filter(&self, word: &str) -> bool {
+        let hsk_level = self.hsk_level(word);
+        self.options
+            .hsk_filter
+            .is_some_and(|filter| hsk_level != 0 && hsk_level <= filter)
+    }
+
+    pub fn segment<'t>(&self, text: &'t str) -> Segmented<'t> {
+        // Segment each chapter separately, to know which chapter each token is in
+        let mut all_tokens = vec![];
+        let mut token_chapters = vec![];
+        for (chapter, text) in split_chapters(text).into_iter().enumerate() {
+            let chapter_tokens = self.jieba.cut(text, false);
+            token_chapters.extend(std::iter::repeat_n(chapter + 1, chapter_tokens.len()));
+            all_tokens.extend(chapter_tokens);
+        }
+        let (words, chapters) = all_tokens
+            .iter()
+            .copied()
+            .zip(token_chapters)
+            .filter(|(token, _)| cjk::is_simplified_chinese(token))
+            .unzip();
+        Segmented {
+            all_tokens,
+            words,
+            chapters,
+        }
+    }
+
+    /// The words in `text` to make cards for, in order.
+    pub fn build(&self, text: &str) -> Vec<CardCandidate<'_>> {
+        let segmented = self.segment(text);
+
+        // Each word in the order it first appears, the chapter it first appears
+        // in, and how many times it appears
+        let mut words = vec![];
+        let mut first_chapters = HashMap::new();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (&token, &chapter) in segmented.words.iter().zip(&segmented.chapters) {
+            *counts.entry(token).or_insert_with(|| {
+                words.push(token);
+                first_chapters.insert(token, chapter);
+                0
+            }) += 1;
+        }
+
+        let mut words_for_cards = vec![];
+        let mut card_counts: HashMap<&str, usize> = HashMap::new();
+        let mut card_chapters = HashMap::new();
+        for word in words {
+            for result in self.dict.get(word) {
+                if !self.wants_card(result) {
+                    continue;
+                }
+
+                // Don't create multiple cards with the same 汉字.
+                let count = card_counts.entry(&result.simplified).or_default();
+                if *count == 0 {
+                    words_for_cards.push(result);
+                    card_chapters.insert(&result.simplified[..], first_chapters[word]);
+                }
+                *count += counts[word];
+            }
+        }
+
+        words_for_cards.retain(|word| card_counts[&word.simplified[..]] >= self.options.min_count);
+        self.sort_words(&mut words_for_cards, &card_counts);
+
+        // Only tag notes with their chapter if there's more than one
+        let has_chapters = segmented.chapters.iter().any(|&chapter| chapter > 1);
+        let sentences: Vec<_> = text.split(['\n', '。']).collect();
+        words_for_cards
+            .into_iter()
+            .map(|word| {
+                let hsk_level = self.hsk_level(&word.simplified);
+                let chapter = card_chapters[&word.simplified[..]];
+                CardCandidate {
+                    word,
+                    count: card_counts[&word.simplified[..]],
+                    chapter,
+                    hsk_level,
+                    example: self
+                        .options
+                        .examples
+                        .then(|| get_example(&sentences, &word.simplified).to_string()),
+                    tags: self.note_tags(word, hsk_level, has_chapters.then_some(chapter)),
+                    subdeck: match self.options.split_by {
+                        SplitBy::None => None,
+                        SplitBy::Chapter => Some(format!("Chapter {chapter}")),
+                        SplitBy::Hsk => Some(match hsk_level {
+                            0 => "Non-HSK".into(),
+                            level => format!("HSK{level}"),
+                        }),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Make cards for the words in `text`, with any audio, and send them to
+    /// each of the places in `output`.
+    pub async fn run(&self, text: &str, output: &OutputOptions) -> anyhow::Result<RunReport> {
+        let candidates = self.build(text);
+        let description = match &output.file_name {
+            Some(file_name) => format!("{} words from {file_name}", candidates.len()),
+            None => format!(
+                "{} words from the text “{}…”",
+                candidates.len(),
+                text.trim().chars().take(20).collect::<String>()
+            ),
+        };
+
+        let mut exporter = output
+            .output
+            .as_ref()
+            .map(|(_, format)| self.exporter(*format, &description, output));
+        let mut preview = output
+            .preview
+            .as_ref()
+            .map(|_| Preview::new(output.note_type.clone()));
+        let mut anki_connect = output
+            .anki_connect
+            .as_ref()
+            .map(|url| AnkiConnect::new(url, &output.deck_name, output.note_type.clone()));
+
+        let audio = match &output.tts {
+            Some(tts) => {
+                let cache = AudioCache::new(tts.cache_dir.clone())?;
+                Audio::fetch(&candidates, &tts.engine, &tts.options, &cache).await
+            }
+            None => Audio::default(),
+        };
+
+        for (position, candidate) in candidates.iter().enumerate() {
+            let card = candidate.card(position, &audio);
+            if let Some(exporter) = &mut exporter {
+                exporter.add_card(&card);
+            }
+            if let Some(preview) = &mut preview {
+                preview.add_card(&card);
+            }
+            if let Some(anki_connect) = &mut anki_connect {
+                anki_connect.add_card(&card);
+            }
+        }
+
+        let media_files = audio.files();
+        if let (Some(exporter), Some((file, _))) = (exporter, &output.output) {
+            let existing_media = match (output.merge_existing, &self.options.existing) {
+                (true, Some(existing)) => existing.media_files()?,
+                _ => vec![],
+            };
+            let existing_media: Vec<_> = existing_media
+                .iter()
+                .map(|f| f.display().to_string())
+                .collect();
+            let mut all_media = media_files.clone();
+            all_media.extend(existing_media.iter().map(|f| &f[..]));
+            exporter.write_to_file(file, all_media)?;
+        }
+        if let (Some(preview), Some(dir)) = (preview, &output.preview) {
+            preview.write_to_file(dir, media_files.clone())?;
+        }
+        let pushed = match anki_connect {
+            Some(anki_connect) => Some(
+                anki_connect
+                    .push(&media_files)
+                    .await
+                    .context("Adding the notes to Anki")?,
+            ),
+            None => None,
+        };
+
+        Ok(RunReport {
+            notes: candidates.len(),
+            audio_failures: audio.failures,
+            pushed,
+        })
+    }
+
+    /// Somewhere to write cards to in `format`. Anki decks get the existing
+    /// notes too, if they're to be merged in.
+    fn exporter(
+        &self,
+        format: Format,
+        description: &str,
+        output: &OutputOptions,
+    ) -> Box<dyn Exporter> {
+        let spacing = output.note_type.style().spacing;
+        match format {
+            Format::Apkg => {
+                let mut anki = Anki::new(&output.deck_name, description, output.note_type.clone());
+                if let (true, Some(existing)) = (output.merge_existing, &self.options.existing) {
+                    for note in existing.notes() {
+                        anki.add_existing_note(note);
+                    }
+                }
+                Box::new(anki)
+            }
+            Format::Tsv => Box::new(DelimitedExporter::tsv(spacing)),
+            Format::Csv => Box::new(DelimitedExporter::csv(spacing)),
+            Format::Json => Box::new(JsonExporter::new(spacing)),
+            Format::Pleco => Box::new(PlecoExporter::new(&output.deck_name)),
+        }
+    }
+
+    /// Whether to make a card for `word`, rather than skipping it as known,
+    /// already carded, or too common or rare.
+    fn wants_card(&self, word: &Word) -> bool {
+        if self.knows(word) {
+            return false;
+        }
+        let word = &word.simplified[..];
+
+        // Skip words that already have cards
+        if self
+            .options
+            .existing
+            .as_ref()
+            .is_some_and(|e| e.contains(word))
+        {
+            return false;
+        }
+
+        // Optionally filter out words that are too common or too rare
+        if let Some(frequency_list) = &self.options.frequency_list {
+            let rank = frequency_list.rank(word);
+            if let (Some(skip_top), Some(rank)) = (self.options.skip_top, rank) {
+                if rank <= skip_top {
+                    return false;
+                }
+            }
+            if let Some(skip_below) = self.options.skip_below {
+                if !matches!(rank, Some(rank) if rank <= skip_below) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn sort_words(&self, words: &mut [&Word], counts: &HashMap<&str, usize>) {
+        match self.options.order {
+            // Words are found in the order they appear
+            Order::FirstOccurrence => {}
+            Order::Frequency => words.sort_by_key(|word| Reverse(counts[&word.simplified[..]])),
+            Order::Hsk => words.sort_by_key(|word| match self.hsk_level(&word.simplified) {
+                0 => u8::MAX,
+                level => level,
+            }),
+            Order::Random => words.shuffle(&mut rand::thread_rng()),
+        }
+    }
+
+    /// Tags for the note for `word`: where it's from (and which chapter, if
+    /// any), its HSK level, whether it's a name, and any other tags in the
+    /// options.
+    fn note_tags(&self, word: &Word, hsk_level: u8, chapter: Option<usize>) -> Vec<String> {
+        let mut tags: Vec<String> = self.options.source.iter().cloned().collect();
+        if let Some(chapter) = chapter {
+            tags.push(format!("chapter-{chapter}"));
+        }
+        tags.push(match hsk_level {
+            0 => "non-hsk".into(),
+            level => format!("hsk{level}"),
+        });
+        if word.is_proper_noun() {
+            tags.push("proper-noun".into());
+        }
+        tags.extend(self.options.tags.iter().cloned());
+        // Anki separates tags with spaces
+        tags.into_iter()
+            .map(|tag| tag.split_whitespace().collect::<Vec<_>>().join("_"))
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+}
+
+fn get_example<'a>(sentences: &[&'a str], word: &str) -> &'a str {
+    sentences
+        .iter()
+        .find(|e| e.contains(word))
+        .expect("There should be at least one sentence that contains each word")
+}
+
+/// Audio for cards: a file for each word (and reading) and example.
+#[derive(Default)]
+pub struct Audio {
+    /// Mapping of text (and reading) to the file containing its audio
+    files: HashMap<(String, Option<PinYin>), String>,
+    /// Whether words have audio for each of their readings
+    supports_readings: bool,
+    /// The words and examples whose audio couldn't be fetched, and why
+    pub failures: Vec<String>,
+}
+
+impl Audio {
+    /// Fetch audio for the words and examples of `candidates` with `backend`,
+    /// or take it from `cache` if it's already there.
+    pub async fn fetch(
+        candidates: &[CardCandidate<'_>],
+        backend: &impl TtsBackend,
+        options: &TtsOptions,
+        cache: &AudioCache,
+    ) -> Self {
+        let mut audio = Self {
+            supports_readings: backend.supports_readings(),
+            ..Self::default()
+        };
+
+        // Several words may share an example, so only ask for each text once
+        let mut seen = HashSet::new();
+        let utterances: Vec<_> = candidates
+            .iter()
+            .flat_map(|candidate| audio.word_utterances(candidate.word))
+            .chain(
+                candidates
+                    .iter()
+                    .filter_map(|candidate| Some((candidate.example.as_deref()?, None))),
+            )
+            .filter(|utterance| seen.insert(*utterance))
+            .collect();
+        let requests = utterances
+            .iter()
+            .map(|&(text, reading)| TtsRequest {
+                text: text.to_string(),
+                reading: reading.cloned(),
+                filename: cache.path_for(backend, text, reading).display().to_string(),
+            })
+            .collect();
+        let results = tts::save_all_to_files(backend, requests, options).await;
+
+        for ((text, reading), result) in utterances.into_iter().zip(results) {
+            match result {
+                Ok(f) => {
+                    audio.files.insert((text.to_string(), reading.cloned()), f);
+                }
+                Err(e) => audio.failures.push(format!("{text}: {e:#}")),
+            }
+        }
+        audio
+    }
+
+    /// Every audio file, to add to a deck.
+    pub fn files(&self) -> Vec<&str> {
+        self.files.values().map(|f| &f[..]).collect()
+    }
+
+    /// What to speak for a word: one utterance per reading if the TTS backend
+    /// can be told which reading to use, and otherwise just the characters.
+    fn word_utterances<'w>(&self, word: &'w Word) -> Vec<(&'w str, Option<&'w PinYin>)> {
+        if self.supports_readings {
+            word.pinyins
+                .keys()
+                .map(|py| (&word.simplified[..], Some(py)))
+                .collect()
+        } else {
+            vec![(&word.simplified[..], None)]
+        }
+    }
+
+    /// The name of the audio file for `text`, as referred to from within the
+    /// package.
+    fn media_name(&self, text: &str, reading: Option<&PinYin>) -> Option<&str> {
+        let file = self.files.get(&(text.to_string(), reading.cloned()))?;
+        Path::new(file).file_name()?.to_str()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::export::fixtures::{bangzhu, word};
+
+    #[test]
+    fn test_note_tags() {
+        let builder = DeckBuilder::new(DeckOptions {
+            source: Some("ST04".into()),
+            tags: vec!["my tag".into(), " ".into()],
+            ..DeckOptions::default()
+        });
+        assert_eq!(
+            builder.note_tags(&bangzhu(), 2, None),
+            ["ST04", "hsk2", "my_tag"]
+        );
+        let xian = word("西安", "Xi1 an1", &["Xi'an"]);
+        assert_eq!(
+            builder.note_tags(&xian, 0, Some(3)),
+            ["ST04", "chapter-3", "non-hsk", "proper-noun", "my_tag"]
+        );
+
+        let builder = DeckBuilder::new(DeckOptions::default());
+        assert_eq!(
+            builder.note_tags(&bangzhu(), 2, Some(1)),
+            ["chapter-1", "hsk2"]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::anki::NoteType;
+use crate::export::{Card, Exporter};
+
+const CSS: &str = "body {background-color: #eee; margin: 0 auto; max-width: 60em;}
+.preview {background-color: white; margin: 16px; padding: 8px; border-radius: 8px;}
+.preview h2 {font-size: 14px; color: grey; font-weight: normal; margin: 0;}
+.sides {display: flex; gap: 8px;}
+.sides .card {flex: 1; border: 1px solid #ddd; padding: 8px;}
+";
+
+/// Cards rendered as Anki would show them, in a static HTML page, to see what
+/// a deck looks like before importing it.
+pub struct Preview {
+    note_type: NoteType,
+    /// The value of each of the note type's fields for each note, with its tags
+    notes: Vec<(Vec<String>, Vec<String>)>,
+}
+
+impl Preview {
+    pub fn new(note_type: NoteType) -> Self {
+        Self {
+            note_type,
+            notes: vec![],
+        }
+    }
+
+    /// The HTML page, with each side of each card that Anki would generate.
+    pub fn html(&self) -> String {
+        let mut body = String::new();
+        for (values, tags) in &self.notes {
+            let fields: HashMap<&str, &str> = self
+                .note_type
+                .fields
+                .iter()
+                .map(|f| &f[..])
+                .zip(values.iter().map(|v| &v[..]))
+                .collect();
+            for template in &self.note_type.templates {
+                let front = render(&template.front, &fields, None);
+                // Like Anki, only make a card if its front has something on it
+                if front.trim().is_empty() {
+                    continue;
+                }
+                let back = render(&template.back, &fields, Some(&front));
+                body += &format!(
+                    "<div class=preview>\n<h2>{} · {}</h2>\n<div class=sides>\n<div class=card>{}</div>\n<div class=card>{}</div>\n</div>\n<div class=tags>{}</div>\n</div>\n",
+                    fields.get("Hanzi").unwrap_or(&""),
+                    template.name,
+                    with_audio(&front),
+                    with_audio(&back),
+                    tags.join(" ")
+                );
+            }
+        }
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=utf-8>\n<style>{}\n{CSS}</style>\n</head>\n<body>\n{body}</body>\n</html>\n",
+            self.note_type.css
+        )
+    }
+}
+
+impl Exporter for Preview {
+    fn add_card(&mut self, card: &Card) {
+        self.notes
+            .push((self.note_type.note_fields(card), card.tags.clone()));
+    }
+
+    /// Write the page to `index.html` in the directory `file`, along with
+    /// `media_files` for it to play.
+    fn write_to_file(&self, file: &str, media_files: Vec<&str>) -> anyhow::Result<()> {
+        let dir = Path::new(file);
+        fs::create_dir_all(dir).with_context(|| format!("Couldn't create {file}"))?;
+        for media_file in media_files {
+            let media_file = Path::new(media_file);
+            if let Some(name) = media_file.file_name() {
+                fs::copy(media_file, dir.join(name))
+                    .with_context(|| format!("Couldn't copy {}", media_file.display()))?;
+            }
+        }
+        fs::write(dir.join("index.html"), self.html())
+            .with_context(|| format!("Couldn't write the preview to {file}"))?;
+        Ok(())
+    }
+}
+
+/// Fill in an Anki card template: `{{Field}}` with the field's value,
+/// `{{#Field}}…{{/Field}}` only if the field isn't empty (or with `^`, only if
+/// it is), `{{type:Field}}` with a box to type the answer in on the front and
+/// the answer on the back, and `{{FrontSide}}` with `front_side`.
+fn render(template: &str, fields: &HashMap<&str, &str>, front_side: Option<&str>) -> String {
+    let mut html = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        html += &rest[..start];
+        let Some(end) = rest[start..].find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = rest[start + 2..start + end].trim();
+        rest = &rest[start + end + 2..];
+        let value = |field: &str| fields.get(field.trim()).copied().unwrap_or_default();
+        if let Some(field) = tag.strip_prefix('#').or_else(|| tag.strip_prefix('^')) {
+            let close = format!("{{{{/{field}}}}}");
+            let (section, after) = rest.split_once(&close).unwrap_or((rest, ""));
+            if value(field).trim().is_empty() != tag.starts_with('#') {
+                html += &render(section, fields, front_side);
+            }
+            rest = after;
+        } else if let Some(field) = tag.strip_prefix("type:") {
+            html += &match front_side {
+                None => format!("<input type=text placeholder=\"{}\">", field.trim()),
+                Some(_) => format!("<div><code class=answer>{}</code></div>", value(field)),
+            };
+        } else if tag == "FrontSide" {
+            html += front_side.unwrap_or_default();
+        } else {
+            html += value(tag);
+        }
+    }
+    html + rest
+}
+
+/// Replace each `[sound:file]` with a player for it.
+fn with_audio(html: &str) -> String {
+    let mut with_audio = String::new();
+    let mut rest = html;
+    while let Some((before, after)) = rest.split_once("[sound:") {
+        let Some((file, after)) = after.split_once(']') else {
+            break;
+        };
+        with_audio += before;
+        with_audio += &format!("<audio controls src=\"{file}\"></audio>");
+        rest = after;
+    }
+    with_audio + rest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::anki::{Side, ToneColours};
+    use crate::export::fixtures::{bangzhu, card};
+    use crate::pinyin::ReadingStyle;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render() {
+        let fields = HashMap::from([("Hanzi", "帮助"), ("NumberedPinyin", ""), ("Count", "2")]);
+        assert_eq!(
+            render("{{Hanzi}} seen {{ Count }} times", &fields, None),
+            "帮助 seen 2 times"
+        );
+        assert_eq!(
+            render(
+                "{{#NumberedPinyin}}{{Hanzi}}{{/NumberedPinyin}}",
+                &fields,
+                None
+            ),
+            ""
+        );
+        assert_eq!(
+            render(
+                "{{^NumberedPinyin}}{{Hanzi}}{{/NumberedPinyin}}!",
+                &fields,
+                None
+            ),
+            "帮助!"
+        );
+        assert_eq!(
+            render("{{type:Count}}", &fields, None),
+            "<input type=text placeholder=\"Count\">"
+        );
+        assert_eq!(
+            render("{{FrontSide}}<hr>{{type:Count}}", &fields, Some("front")),
+            "front<hr><div><code class=answer>2</code></div>"
+        );
+        assert_eq!(
+            with_audio("a [sound:x.mp3] b"),
+            "a <audio controls src=\"x.mp3\"></audio> b"
+        );
+    }
+
+    #[test]
+    fn test_preview() {
+        let word = bangzhu();
+        let note_type = NoteType::new(
+            &ToneColours::default(),
+            &[Side::CeToEn, Side::TypePinyin],
+            true,
+            ReadingStyle::default(),
+            &[],
+            false,
+        );
+        let mut preview = Preview::new(note_type);
+        preview.add_card(&Card {
+            sounds: vec![(None, "帮助.mp3")],
+            ..card(&word)
+        });
+        let html = preview.html();
+        assert!(html.contains(".tone1 {"));
+        assert!(html.contains("<h2>帮助 · Card 2</h2>"));
+        assert!(html.contains("<h2>帮助 · Card 3</h2>"));
+        assert!(html.contains("<div class=chinese>帮助</div><input type=text"));
+        assert!(html.contains("<code class=answer>bang1 zhu4</code>"));
+        assert!(html.contains("请帮助我"));
+        assert!(html.contains("<audio controls src=\"帮助.mp3\"></audio>"));
+        assert!(html.contains("Seen 2 times in the text"));
+        assert!(html.contains("<div class=tags>hsk2</div>"));
+        assert!(!html.contains("{{"));
+
+        let dir = TempDir::new().unwrap();
+        let media = dir.path().join("帮助.mp3");
+        fs::write(&media, b"ID3").unwrap();
+        let out = dir.path().join("preview");
+        preview
+            .write_to_file(
+                &out.display().to_string(),
+                vec![&media.display().to_string()],
+            )
+            .unwrap();
+        assert_eq!(fs::read_to_string(out.join("index.html")).unwrap(), html);
+        assert_eq!(fs::read(out.join("帮助.mp3")).unwrap(), b"ID3");
+    }
+}
@@ -0,0 +1,175 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How hard a text is: how much of it is covered by each HSK level and by the
+/// words the user already knows, and which words they don't know.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Report {
+    /// The number of words in the text, counting repeats
+    pub tokens: usize,
+    /// The number of distinct words in the text
+    pub types: usize,
+    pub hsk_coverage: Vec<HskCoverage>,
+    /// The percentage of tokens that are known, if known words were given
+    pub known_coverage: Option<f64>,
+    /// Words that aren't known, most frequent first
+    pub unknown_words: Vec<WordCount>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct HskCoverage {
+    /// The HSK level, or None for words that aren't in HSK
+    pub level: Option<u8>,
+    /// The percentage of tokens at this level
+    pub percent: f64,
+    /// The percentage of tokens at or below this level
+    pub cumulative_percent: f64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WordCount {
+    pub word: String,
+    pub count: usize,
+}
+
+impl Report {
+    /// Report on `tokens`, the words of a text in order. `hsk_level` gives a
+    /// word's HSK level (0 if it isn't in HSK), and `is_known` whether the user
+    /// knows a word, if that's known.
+    pub fn new(
+        tokens: &[&str],
+        hsk_level: impl Fn(&str) -> u8,
+        is_known: Option<&dyn Fn(&str) -> bool>,
+    ) -> Self {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut words = vec![];
+        for token in tokens {
+            *counts.entry(token).or_insert_with(|| {
+                words.push(*token);
+                0
+            }) += 1;
+        }
+        let percent = |count: usize| {
+            if tokens.is_empty() {
+                0.0
+            } else {
+                100.0 * count as f64 / tokens.len() as f64
+            }
+        };
+
+        let mut level_counts = [0; 7];
+        for token in tokens {
+            level_counts[usize::from(hsk_level(token)).min(6)] += 1;
+        }
+        let mut cumulative = 0;
+        let mut hsk_coverage: Vec<_> = (1..=6)
+            .map(|level| {
+                cumulative += level_counts[level];
+                HskCoverage {
+                    level: Some(level as u8),
+                    percent: percent(level_counts[level]),
+                    cumulative_percent: percent(cumulative),
+                }
+            })
+            .collect();
+        hsk_coverage.push(HskCoverage {
+            level: None,
+            percent: percent(level_counts[0]),
+            cumulative_percent: 100.0,
+        });
+
+        let known_coverage =
+            is_known.map(|is_known| percent(tokens.iter().filter(|t| is_known(t)).count()));
+
+        // Sorted stably, so words that appear as often are in the order they first appear
+        let mut unknown_words: Vec<_> = words
+            .into_iter()
+            .filter(|word| !is_known.is_some_and(|is_known| is_known(word)))
+            .map(|word| WordCount {
+                word: word.to_string(),
+                count: counts[word],
+            })
+            .collect();
+        unknown_words.sort_by_key(|w| std::cmp::Reverse(w.count));
+
+        Self {
+            tokens: tokens.len(),
+            types: counts.len(),
+            hsk_coverage,
+            known_coverage,
+            unknown_words,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Tokens: {}", self.tokens)?;
+        writeln!(f, "Types: {}", self.types)?;
+        writeln!(f, "HSK coverage:")?;
+        for coverage in &self.hsk_coverage {
+            match coverage.level {
+                Some(level) => writeln!(
+                    f,
+                    "  HSK {level}: {:.1}% ({:.1}% at or below)",
+                    coverage.percent, coverage.cumulative_percent
+                )?,
+                None => writeln!(f, "  Not in HSK: {:.1}%", coverage.percent)?,
+            }
+        }
+        if let Some(known_coverage) = self.known_coverage {
+            writeln!(f, "Known: {known_coverage:.1}%")?;
+        }
+        writeln!(f, "Unknown words, most frequent first:")?;
+        for word in &self.unknown_words {
+            writeln!(f, "  {} {}", word.word, word.count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let tokens = ["我", "帮助", "你", "你", "帮助", "西安", "你"];
+        let hsk_level = |word: &str| match word {
+            "我" | "你" => 1,
+            "帮助" => 2,
+            _ => 0,
+        };
+        let is_known = |word: &str| word == "我" || word == "你";
+        let report = Report::new(&tokens, hsk_level, Some(&is_known));
+
+        assert_eq!(report.tokens, 7);
+        assert_eq!(report.types, 4);
+        let percent = |p: f64| (p * 10.0).round() / 10.0;
+        assert_eq!(percent(report.hsk_coverage[0].percent), 57.1);
+        assert_eq!(percent(report.hsk_coverage[1].percent), 28.6);
+        assert_eq!(percent(report.hsk_coverage[1].cumulative_percent), 85.7);
+        assert_eq!(report.hsk_coverage[6].level, None);
+        assert_eq!(percent(report.hsk_coverage[6].percent), 14.3);
+        assert_eq!(report.known_coverage.map(percent), Some(57.1));
+        assert_eq!(
+            report.unknown_words,
+            vec![
+                WordCount {
+                    word: "帮助".into(),
+                    count: 2
+                },
+                WordCount {
+                    word: "西安".into(),
+                    count: 1
+                }
+            ]
+        );
+
+        let report = Report::new(&tokens, hsk_level, None);
+        assert_eq!(report.known_coverage, None);
+        assert_eq!(report.unknown_words.len(), 4);
+        assert_eq!(report.unknown_words[0].word, "你");
+    }
+}
@@ -1,39 +1,624 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
+use futures::{future::BoxFuture, stream, StreamExt};
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use std::path::Path;
-use tokio::{fs::File, io::AsyncWriteExt};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    process::Command,
+    sync::Mutex,
+    time::{sleep, sleep_until, Instant},
+};
+
+use crate::dict::PinYin;
 
 /// https://url.spec.whatwg.org/#fragment-percent-encode-set
 const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
 
+/// Something that can turn Chinese text into an audio file.
+pub trait TtsBackend: Send + Sync {
+    /// Synthesise `text`, writing the audio to `path`. If the backend
+    /// supports readings then `reading` is the intended pronunciation.
+    fn synthesise<'a>(
+        &'a self,
+        text: &'a str,
+        reading: Option<&'a PinYin>,
+        path: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Whether the backend can be told which reading to use for polyphonic
+    /// words (like 行 or 长), rather than picking one itself.
+    fn supports_readings(&self) -> bool {
+        false
+    }
+
+    /// The extension of the audio files produced, e.g. "mp3".
+    fn extension(&self) -> &str;
+
+    /// Identifies the backend and all of its settings that affect the audio
+    /// produced (such as voice and speed), for use in cache keys.
+    fn cache_key(&self) -> String;
+}
+
+/// Google Translate's (undocumented) TTS endpoint.
+#[derive(Debug, Clone)]
+pub struct GoogleTts {
+    client: reqwest::Client,
+    /// The language code, e.g. "zh-CN" or "zh-TW"
+    voice: String,
+    /// Playback speed, where 1 is normal
+    speed: String,
+}
+
+impl Default for GoogleTts {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            voice: "zh-CN".into(),
+            speed: "1".into(),
+        }
+    }
+}
+
+impl TtsBackend for GoogleTts {
+    fn synthesise<'a>(
+        &'a self,
+        text: &'a str,
+        _reading: Option<&'a PinYin>,
+        path: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let len = text.len();
+            let encoded = utf8_percent_encode(text, FRAGMENT);
+            let rep = self.client.get(format!("https://translate.google.com/translate_tts?ie=UTF-8&q={}&tl={}&total=1&idx=0&textlen={}&ttsspeed={}&client=tw-ob", encoded, self.voice, len, self.speed))
+              .send()
+              .await
+              .with_context(|| {format!("Getting tts for {text}")})?;
+            let mut file = File::create(path).await?;
+            let mut bytes = rep
+                .bytes()
+                .await
+                .with_context(|| format!("Getting bytes for {text}"))?;
+            file.write_all_buf(&mut bytes)
+                .await
+                .context("Writing to file")?;
+            Ok(())
+        })
+    }
+
+    fn extension(&self) -> &str {
+        "mp3"
+    }
+
+    fn cache_key(&self) -> String {
+        format!("google|{}|{}", self.voice, self.speed)
+    }
+}
+
+/// A local command-line engine, run once per piece of text.
+///
+/// The command is a template in which `{text}` is replaced by the text to be
+/// spoken, and `{wav}` or `{mp3}` by the path of the audio file that the
+/// command should write. If there is no `{text}` then the text is written to
+/// the command's stdin instead. `{voice}` and `{speed}` are replaced by the
+/// configured voice and speed.
+///
+/// Commands can be told which reading to use with `{pinyin}`, replaced by
+/// numbered pinyin like "xing2", or `{ssml}`, replaced by an SSML document
+/// with a pinyin `<phoneme>` hint.
+#[derive(Debug, Clone)]
+pub struct CommandTts {
+    template: Vec<String>,
+    extension: String,
+    voice: String,
+    speed: String,
+}
+
+impl CommandTts {
+    pub fn espeak_ng() -> Self {
+        Self {
+            voice: "cmn".into(),
+            speed: "175".into(),
+            ..("espeak-ng -v {voice} -s {speed} -w {wav} {text}"
+                .parse()
+                .unwrap())
+        }
+    }
+
+    pub fn piper() -> Self {
+        Self {
+            voice: "zh_CN-huayan-medium".into(),
+            speed: "1".into(),
+            ..("piper --model {voice} --length_scale {speed} --output_file {wav}"
+                .parse()
+                .unwrap())
+        }
+    }
+}
+
+impl FromStr for CommandTts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let template: Vec<String> = s.split_whitespace().map(String::from).collect();
+        if template.is_empty() {
+            return Err("Expected a TTS command, got nothing".into());
+        }
+        let extension = ["wav", "mp3"]
+            .into_iter()
+            .find(|ext| {
+                template
+                    .iter()
+                    .any(|arg| arg.contains(&format!("{{{ext}}}")))
+            })
+            .ok_or_else(|| {
+                format!("Expected '{s}' to contain {{wav}} or {{mp3}} for the output file")
+            })?;
+        Ok(Self {
+            template,
+            extension: extension.into(),
+            voice: String::new(),
+            speed: String::new(),
+        })
+    }
+}
+
+impl TtsBackend for CommandTts {
+    fn synthesise<'a>(
+        &'a self,
+        text: &'a str,
+        reading: Option<&'a PinYin>,
+        path: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let output = path.to_str().context("Non-UTF-8 audio path")?;
+            let output_placeholder = format!("{{{}}}", self.extension);
+            let text_on_stdin = !self.template.iter().any(|arg| arg.contains("{text}"));
+            let pinyin = reading.map(PinYin::to_numbered).unwrap_or_default();
+            let mut args = self.template.iter().map(|arg| {
+                arg.replace(&output_placeholder, output)
+                    .replace("{voice}", &self.voice)
+                    .replace("{speed}", &self.speed)
+                    .replace("{pinyin}", &pinyin)
+                    .replace("{ssml}", &to_ssml(text, reading))
+                    .replace("{text}", text)
+            });
+
+            let mut command = Command::new(args.next().unwrap());
+            command
+                .args(args)
+                .stdin(if text_on_stdin {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                })
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped());
+            let mut child = command
+                .spawn()
+                .with_context(|| format!("Running {}", self.template[0]))?;
+            if let Some(mut stdin) = child.stdin.take() {
+                match stdin.write_all(text.as_bytes()).await {
+                    // The command doesn't have to read the text, e.g. if it only needs {pinyin}
+                    Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+                    other => other.context("Writing text to TTS command")?,
+                }
+            }
+            let result = child.wait_with_output().await?;
+            if !result.status.success() {
+                bail!(
+                    "{} failed for {text}: {}",
+                    self.template[0],
+                    String::from_utf8_lossy(&result.stderr)
+                );
+            }
+            Ok(())
+        })
+    }
+
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    fn supports_readings(&self) -> bool {
+        self.template
+            .iter()
+            .any(|arg| arg.contains("{pinyin}") || arg.contains("{ssml}"))
+    }
+
+    fn cache_key(&self) -> String {
+        format!(
+            "command|{}|{}|{}",
+            self.template.join(" "),
+            self.voice,
+            self.speed
+        )
+    }
+}
+
+fn to_ssml(text: &str, reading: Option<&PinYin>) -> String {
+    let text = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    match reading {
+        Some(py) => format!(
+            r#"<speak><phoneme alphabet="pinyin" ph="{}">{text}</phoneme></speak>"#,
+            py.to_numbered()
+        ),
+        None => format!("<speak>{text}</speak>"),
+    }
+}
+
+/// The TTS backend to use
+#[derive(Debug, Clone)]
+pub enum TtsEngine {
+    Google(GoogleTts),
+    Command(CommandTts),
+}
+
+impl TtsEngine {
+    /// Override the backend's default voice - a language code for Google, or
+    /// whatever `{voice}` should be replaced with for a command.
+    pub fn with_voice(mut self, voice: String) -> Self {
+        match &mut self {
+            TtsEngine::Google(google) => google.voice = voice,
+            TtsEngine::Command(command) => command.voice = voice,
+        }
+        self
+    }
+
+    /// Override the backend's default speed, in whatever units the backend uses.
+    pub fn with_speed(mut self, speed: String) -> Self {
+        match &mut self {
+            TtsEngine::Google(google) => google.speed = speed,
+            TtsEngine::Command(command) => command.speed = speed,
+        }
+        self
+    }
+}
+
+impl Default for TtsEngine {
+    fn default() -> Self {
+        TtsEngine::Google(GoogleTts::default())
+    }
+}
+
+impl FromStr for TtsEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "google" => Ok(TtsEngine::default()),
+            "espeak-ng" | "espeak" => Ok(TtsEngine::Command(CommandTts::espeak_ng())),
+            "piper" => Ok(TtsEngine::Command(CommandTts::piper())),
+            _ => Ok(TtsEngine::Command(s.parse()?)),
+        }
+    }
+}
+
+impl TtsBackend for TtsEngine {
+    fn synthesise<'a>(
+        &'a self,
+        text: &'a str,
+        reading: Option<&'a PinYin>,
+        path: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        match self {
+            TtsEngine::Google(google) => google.synthesise(text, reading, path),
+            TtsEngine::Command(command) => command.synthesise(text, reading, path),
+        }
+    }
+
+    fn supports_readings(&self) -> bool {
+        match self {
+            TtsEngine::Google(google) => google.supports_readings(),
+            TtsEngine::Command(command) => command.supports_readings(),
+        }
+    }
+
+    fn extension(&self) -> &str {
+        match self {
+            TtsEngine::Google(google) => google.extension(),
+            TtsEngine::Command(command) => command.extension(),
+        }
+    }
+
+    fn cache_key(&self) -> String {
+        match self {
+            TtsEngine::Google(google) => google.cache_key(),
+            TtsEngine::Command(command) => command.cache_key(),
+        }
+    }
+}
+
 pub async fn save_to_file(
-    client: &reqwest::Client,
+    backend: &impl TtsBackend,
     text: &str,
+    reading: Option<&PinYin>,
     filename: String,
 ) -> anyhow::Result<String> {
-    // Short-circuit if the file already exists
-    if Path::new(&filename).exists() {
+    // Short-circuit if we already have the audio
+    if is_audio_file(Path::new(&filename)).await {
         return Ok(filename);
     }
 
-    let len = text.len();
-    let encoded = utf8_percent_encode(text, FRAGMENT);
-    let rep = client.get(format!("https://translate.google.com/translate_tts?ie=UTF-8&q={}&tl=zh-CN&total=1&idx=0&textlen={}&tl=zh-CN&client=tw-ob", encoded, len))
-      .send()
-      .await
-      .with_context(|| {format!("Getting tts for {text}")})?;
     if let Some(parent_dir) = Path::new(&filename).parent() {
         tokio::fs::create_dir_all(parent_dir)
             .await
             .context("Creating directory")?;
     }
-    let mut file = File::create(&filename).await?;
-    let mut bytes = rep
-        .bytes()
+    let result = match backend
+        .synthesise(text, reading, Path::new(&filename))
         .await
-        .with_context(|| format!("Getting bytes for {text}"))?;
-    file.write_all_buf(&mut bytes)
+    {
+        Ok(()) if !is_audio_file(Path::new(&filename)).await => {
+            Err(anyhow::anyhow!("Didn't get any audio for {text}"))
+        }
+        other => other,
+    };
+    if result.is_err() {
+        // Don't leave a partial file around to be mistaken for real audio later
+        let _ = tokio::fs::remove_file(&filename).await;
+    }
+    result.map(|()| filename)
+}
+
+/// Whether `path` exists and looks like an audio file, rather than (say) an
+/// empty file or an error page.
+pub async fn is_audio_file(path: &Path) -> bool {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => is_audio(&bytes),
+        Err(_) => false,
+    }
+}
+
+fn is_audio(bytes: &[u8]) -> bool {
+    match bytes {
+        [b'I', b'D', b'3', ..] | [b'O', b'g', b'g', b'S', ..] | [b'f', b'L', b'a', b'C', ..] => {
+            true
+        }
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => true,
+        // An MPEG frame header without any ID3 tag
+        [0xff, second, ..] => second & 0xe0 == 0xe0,
+        _ => false,
+    }
+}
+
+/// How to go about fetching audio for many pieces of text
+#[derive(Debug, Clone)]
+pub struct TtsOptions {
+    /// The maximum number of requests in flight at once, at least 1
+    pub concurrency: usize,
+    /// How many times to retry a failed request, backing off exponentially
+    pub retries: u32,
+    /// Optionally, the maximum number of requests to start per second
+    pub rate_limit: Option<f64>,
+}
+
+impl Default for TtsOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            retries: 3,
+            rate_limit: None,
+        }
+    }
+}
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long to wait before retrying after `attempt` failed attempts: doubling
+/// each time, up to `MAX_BACKOFF`.
+fn backoff(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt)
+        .map_or(MAX_BACKOFF, |factor| INITIAL_BACKOFF.saturating_mul(factor))
+        .min(MAX_BACKOFF)
+}
+
+/// Spaces out requests so that no more than a given number start per second.
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        // Hold the lock while sleeping, so that waiters are released one at a time
+        let mut next = self.next.lock().await;
+        sleep_until(*next).await;
+        *next = Instant::now().max(*next) + self.interval;
+    }
+}
+
+/// A piece of text to save audio for
+#[derive(Debug, Clone)]
+pub struct TtsRequest {
+    pub text: String,
+    /// The intended reading, for backends that support them
+    pub reading: Option<PinYin>,
+    pub filename: String,
+}
+
+/// Save audio for each request, returning the result for each in the same
+/// order. One failure doesn't stop the others from being fetched.
+pub async fn save_all_to_files(
+    backend: &impl TtsBackend,
+    requests: Vec<TtsRequest>,
+    options: &TtsOptions,
+) -> Vec<anyhow::Result<String>> {
+    let rate_limiter = options.rate_limit.map(RateLimiter::new);
+    stream::iter(requests)
+        .map(|request| {
+            let rate_limiter = rate_limiter.as_ref();
+            async move {
+                let mut attempt = 0;
+                loop {
+                    if !Path::new(&request.filename).exists() {
+                        if let Some(rate_limiter) = rate_limiter {
+                            rate_limiter.wait().await;
+                        }
+                    }
+                    match save_to_file(
+                        backend,
+                        &request.text,
+                        request.reading.as_ref(),
+                        request.filename.clone(),
+                    )
+                    .await
+                    {
+                        Ok(f) => return Ok(f),
+                        Err(e) if attempt >= options.retries => return Err(e),
+                        Err(_) => {
+                            sleep(backoff(attempt)).await;
+                            attempt += 1;
+                        }
+                    }
+                }
+            }
+        })
+        .buffered(options.concurrency)
+        .collect()
         .await
-        .context("Writing to file")?;
-    Ok(filename)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+
+    /// Fails the first `failures` attempts at each piece of text
+    struct FlakyTts {
+        failures: usize,
+        attempts: std::sync::Mutex<std::collections::HashMap<String, usize>>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl TtsBackend for FlakyTts {
+        fn synthesise<'a>(
+            &'a self,
+            text: &'a str,
+            _reading: Option<&'a PinYin>,
+            path: &'a Path,
+        ) -> BoxFuture<'a, anyhow::Result<()>> {
+            Box::pin(async move {
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                sleep(Duration::from_millis(10)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                let attempts = {
+                    let mut attempts = self.attempts.lock().unwrap();
+                    let n = attempts.entry(text.to_string()).or_default();
+                    *n += 1;
+                    *n
+                };
+                if attempts <= self.failures {
+                    bail!("Attempt {attempts} at {text} failed");
+                }
+                tokio::fs::write(path, b"ID3").await?;
+                Ok(())
+            })
+        }
+
+        fn extension(&self) -> &str {
+            "mp3"
+        }
+
+        fn cache_key(&self) -> String {
+            format!("flaky|{}", self.failures)
+        }
+    }
+
+    #[test]
+    fn test_is_audio() {
+        assert!(is_audio(b"ID3\x04\x00"));
+        assert!(is_audio(b"RIFF\x24\x08\x00\x00WAVEfmt "));
+        assert!(is_audio(&[0xff, 0xfb, 0x90, 0x64]));
+        assert!(!is_audio(b""));
+        assert!(!is_audio(b"<html><body>Too many requests</body></html>"));
+    }
+
+    #[tokio::test]
+    async fn test_save_all_retries_and_limits_concurrency() {
+        let dir = TempDir::new().unwrap();
+        let backend = FlakyTts {
+            failures: 2,
+            attempts: Default::default(),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+        };
+        let requests = ["一", "二", "三", "四", "五"]
+            .into_iter()
+            .map(|t| TtsRequest {
+                text: t.to_string(),
+                reading: None,
+                filename: dir.path().join(format!("{t}.mp3")).display().to_string(),
+            })
+            .collect();
+
+        let options = TtsOptions {
+            concurrency: 2,
+            retries: 1,
+            rate_limit: None,
+        };
+        let results = save_all_to_files(&backend, requests, &options).await;
+        // Only one retry isn't enough to get past two failures
+        assert!(results.iter().all(|r| r.is_err()));
+        assert_eq!(backend.max_in_flight.load(Ordering::SeqCst), 2);
+
+        let requests = vec![TtsRequest {
+            text: "一".to_string(),
+            reading: None,
+            filename: dir.path().join("一.mp3").display().to_string(),
+        }];
+        let results = save_all_to_files(&backend, requests, &TtsOptions::default()).await;
+        assert!(results[0].is_ok());
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), INITIAL_BACKOFF);
+        assert_eq!(backoff(2), INITIAL_BACKOFF * 4);
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let command: CommandTts =
+            "edge-tts --voice zh-CN-XiaoxiaoNeural --text {text} --write-media {mp3}"
+                .parse()
+                .unwrap();
+        assert_eq!(command.extension(), "mp3");
+        assert_eq!(CommandTts::piper().extension(), "wav");
+        assert!("edge-tts --text {text}".parse::<CommandTts>().is_err());
+    }
+
+    #[test]
+    fn test_supports_readings() {
+        assert!(!TtsEngine::default().supports_readings());
+        assert!(!CommandTts::espeak_ng().supports_readings());
+        let command: CommandTts = "my-tts --ssml {ssml} --out {wav}".parse().unwrap();
+        assert!(command.supports_readings());
+
+        let reading = PinYin(vec!["hang2".into()]);
+        assert_eq!(
+            to_ssml("行", Some(&reading)),
+            r#"<speak><phoneme alphabet="pinyin" ph="hang2">行</phoneme></speak>"#
+        );
+    }
 }
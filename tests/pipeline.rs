@@ -0,0 +1,212 @@
+use tempfile::TempDir;
+use zw_to_anki::anki::{NoteType, ToneColours};
+use zw_to_anki::export::Format;
+use zw_to_anki::known::KnownWords;
+use zw_to_anki::pinyin::ReadingStyle;
+use zw_to_anki::pipeline::{
+    Audio, CardCandidate, DeckBuilder, DeckOptions, Order, OutputOptions, SplitBy,
+};
+
+const TEXT: &str = "你好，我在西安。\n他在西安帮助我。";
+
+fn hanzi<'a>(candidates: &'a [CardCandidate]) -> Vec<&'a str> {
+    candidates
+        .iter()
+        .map(|candidate| &candidate.word.simplified[..])
+        .collect()
+}
+
+fn find<'a, 'd>(candidates: &'a [CardCandidate<'d>], word: &str) -> &'a CardCandidate<'d> {
+    candidates
+        .iter()
+        .find(|candidate| candidate.word.simplified == word)
+        .unwrap_or_else(|| panic!("There should be a card for {word}"))
+}
+
+#[test]
+fn test_build() {
+    let builder = DeckBuilder::new(DeckOptions::default());
+    let candidates = builder.build(TEXT);
+    assert_eq!(
+        hanzi(&candidates),
+        ["你好", "我", "在", "西安", "他", "帮助"]
+    );
+
+    let xian = find(&candidates, "西安");
+    assert_eq!(xian.count, 2);
+    assert_eq!(xian.example.as_deref(), Some("你好，我在西安"));
+    assert!(xian.tags.contains(&"proper-noun".to_string()));
+    assert_eq!(xian.subdeck, None);
+
+    let bangzhu = find(&candidates, "帮助");
+    assert_eq!(bangzhu.count, 1);
+    assert_eq!(bangzhu.example.as_deref(), Some("他在西安帮助我"));
+    assert_eq!(bangzhu.hsk_level, 2);
+}
+
+#[test]
+fn test_filters() {
+    let builder = DeckBuilder::new(DeckOptions {
+        hsk_filter: Some(1),
+        known_words: Some(KnownWords::parse("西安\n")),
+        examples: false,
+        ..DeckOptions::default()
+    });
+    let candidates = builder.build(TEXT);
+    let words = hanzi(&candidates);
+    assert!(!words.contains(&"你好"));
+    assert!(!words.contains(&"我"));
+    assert!(!words.contains(&"西安"));
+    assert!(words.contains(&"帮助"));
+    assert!(candidates
+        .iter()
+        .all(|candidate| candidate.example.is_none()));
+
+    let builder = DeckBuilder::new(DeckOptions {
+        min_count: 2,
+        ..DeckOptions::default()
+    });
+    assert_eq!(hanzi(&builder.build(TEXT)), ["我", "在", "西安"]);
+}
+
+#[test]
+fn test_order() {
+    // Most frequent first, and otherwise in the order they're found
+    let builder = DeckBuilder::new(DeckOptions {
+        order: Order::Frequency,
+        ..DeckOptions::default()
+    });
+    let candidates = builder.build(TEXT);
+    assert_eq!(
+        hanzi(&candidates),
+        ["我", "在", "西安", "你好", "他", "帮助"]
+    );
+
+    // The position in this order is what the sort key holds
+    let note_type = NoteType::new(
+        &ToneColours::default(),
+        &[],
+        false,
+        ReadingStyle::default(),
+        &[],
+        true,
+    );
+    let sort_key = note_type
+        .fields
+        .iter()
+        .position(|field| field == "SortKey")
+        .unwrap();
+    let audio = Audio::default();
+    let sort_keys: Vec<_> = candidates
+        .iter()
+        .enumerate()
+        .map(|(position, candidate)| {
+            note_type.note_fields(&candidate.card(position, &audio))[sort_key].clone()
+        })
+        .collect();
+    assert_eq!(
+        sort_keys,
+        ["000000", "000001", "000002", "000003", "000004", "000005"]
+    );
+
+    // Lowest HSK level first, with words in no level last
+    let builder = DeckBuilder::new(DeckOptions {
+        order: Order::Hsk,
+        ..DeckOptions::default()
+    });
+    let candidates = builder.build(TEXT);
+    let words = hanzi(&candidates);
+    let levels: Vec<_> = words
+        .iter()
+        .map(|word| match builder.hsk_level(word) {
+            0 => u8::MAX,
+            level => level,
+        })
+        .collect();
+    assert!(
+        levels.windows(2).all(|pair| pair[0] <= pair[1]),
+        "{words:?}"
+    );
+    assert_eq!(words[..2], ["你好", "我"]);
+}
+
+#[test]
+fn test_chapters_and_tags() {
+    let builder = DeckBuilder::new(DeckOptions {
+        split_by: SplitBy::Chapter,
+        source: Some("ST04".into()),
+        tags: vec!["my tag".into()],
+        ..DeckOptions::default()
+    });
+    let candidates = builder.build("Chapter 1\n你好。\nChapter 2\n帮助你好。");
+    assert_eq!(hanzi(&candidates), ["你好", "帮助"]);
+
+    let nihao = find(&candidates, "你好");
+    assert_eq!((nihao.chapter, nihao.count), (1, 2));
+    assert_eq!(nihao.subdeck.as_deref(), Some("Chapter 1"));
+
+    let bangzhu = find(&candidates, "帮助");
+    assert_eq!(bangzhu.chapter, 2);
+    assert_eq!(bangzhu.subdeck.as_deref(), Some("Chapter 2"));
+    assert_eq!(bangzhu.tags, ["ST04", "chapter-2", "hsk2", "my_tag"]);
+
+    // Without audio, cards have no sounds
+    let audio = Audio::default();
+    let card = bangzhu.card(1, &audio);
+    assert_eq!(card.position, 1);
+    assert_eq!(card.example, Some("帮助你好"));
+    assert!(card.sounds.is_empty());
+    assert_eq!(card.example_sound, None);
+}
+
+#[test]
+fn test_segment() {
+    let builder = DeckBuilder::new(DeckOptions::default());
+    let segmented = builder.segment(TEXT);
+    assert_eq!(segmented.all_tokens.concat(), TEXT);
+    assert_eq!(
+        segmented.words,
+        ["你好", "我", "在", "西安", "他", "在", "西安", "帮助", "我"]
+    );
+    assert!(segmented.chapters.iter().all(|&chapter| chapter == 1));
+}
+
+#[tokio::test]
+async fn test_run() {
+    let dir = TempDir::new().unwrap();
+    let tsv = dir.path().join("ST04.tsv").display().to_string();
+    let preview = dir.path().join("preview").display().to_string();
+    let builder = DeckBuilder::new(DeckOptions::default());
+    let report = builder
+        .run(
+            TEXT,
+            &OutputOptions {
+                deck_name: "ST04".into(),
+                file_name: Some("ST04.txt".into()),
+                note_type: NoteType::new(
+                    &ToneColours::default(),
+                    &[],
+                    false,
+                    ReadingStyle::default(),
+                    &[],
+                    false,
+                ),
+                output: Some((tsv.clone(), Format::Tsv)),
+                merge_existing: false,
+                preview: Some(preview.clone()),
+                anki_connect: None,
+                tts: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(report.notes, 6);
+    assert!(report.audio_failures.is_empty());
+    assert!(report.pushed.is_none());
+
+    let rows = std::fs::read_to_string(&tsv).unwrap();
+    assert_eq!(rows.lines().count(), 6);
+    assert!(rows.starts_with("你好\t"));
+    let html = std::fs::read_to_string(format!("{preview}/index.html")).unwrap();
+    assert!(html.contains("<h2>西安 · Card 2</h2>"));
+}